@@ -10,18 +10,30 @@ use futures::{
 };
 use serde::{Deserialize, Serialize};
 
+use std::sync::Arc;
+
 use crate::{
-    database::data_types::TaskData,
-    message::{self, Caller, LocalMessage, LocalMessageModule, Message},
+    blockchain::proof::StateTrie,
+    database::{
+        cache::CachedDB,
+        data_types::{NodeData, TaskData},
+    },
+    message::{self, Caller, LocalMessage, LocalMessageModule, Message, ReqError},
 };
 
 use self::config::ApiConfig;
 pub mod config;
 
+/// Most items returned by one page of a list api.
+pub const MAX_PAGE_LIMIT: usize = 100;
+
 #[derive(Clone)]
 pub struct ApiModule {
     caller: Caller,
     conf: ApiConfig,
+    pub blockchain_caller: Option<Caller>,
+    pub task_caller: Option<Caller>,
+    pub db_cache: Option<Arc<CachedDB>>,
 }
 
 impl ApiModule {
@@ -29,6 +41,9 @@ impl ApiModule {
         ApiModule {
             caller: caller,
             conf,
+            blockchain_caller: None,
+            task_caller: None,
+            db_cache: None,
         }
     }
 }
@@ -42,6 +57,15 @@ pub fn run(api: ApiModule) -> Server {
             .service(keeper_init)
             .service(worker_active)
             .service(get_keeper_node_list)
+            .service(get_task_list)
+            .service(get_history_tasks)
+            .service(get_history_nodes)
+            .service(get_history_balance)
+            .service(get_chain_sync_status)
+            .service(get_block_rewards)
+            .service(get_chain_finality)
+            .service(get_state_proof)
+            .service(get_db_cache_metrics)
     })
     .bind((api_config.host, api_config.port))
     .unwrap()
@@ -113,9 +137,318 @@ async fn worker_active(
     Ok(HttpResponse::Ok().json(ApiResponse::success()))
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct PageQuery<K> {
+    start: Option<K>,
+    limit: Option<usize>,
+}
+
+impl<K> PageQuery<K> {
+    /// `fetch_limit` one more item than the page is fetched to know where the next page starts.
+    fn fetch_limit(&self) -> usize {
+        self.limit.unwrap_or(MAX_PAGE_LIMIT).min(MAX_PAGE_LIMIT) + 1
+    }
+}
+
+/// `PageDto` a page of items, `next` is the start of the next page if there is one.
+#[derive(Debug, Serialize, Deserialize)]
+struct PageDto<T, K> {
+    items: Vec<T>,
+    next: Option<K>,
+}
+
+impl<T, K> PageDto<T, K> {
+    fn new(mut items: Vec<T>, fetch_limit: usize, key: impl Fn(&T) -> K) -> Self {
+        let next = if items.len() == fetch_limit {
+            items.pop().map(|item| key(&item))
+        } else {
+            None
+        };
+        PageDto { items, next }
+    }
+}
+
 #[get("/keeper/node_list")]
-async fn get_keeper_node_list(api_module: Data<ApiModule>) -> Result<HttpResponse, Error> {
-    Ok(HttpResponse::Ok().json(ApiResponse::success()))
+async fn get_keeper_node_list(
+    api_module: Data<ApiModule>,
+    query: web::Query<PageQuery<String>>,
+) -> Result<HttpResponse, Error> {
+    let query = query.into_inner();
+    let fetch_limit = query.fetch_limit();
+    let res = api_module
+        .caller
+        .clone()
+        .call(Message::LocalMessage(LocalMessage::ReqNodePage(
+            query.start,
+            fetch_limit,
+        )))
+        .await;
+    match res {
+        Ok(Some(Message::LocalMessage(LocalMessage::AckNodePage(nodes)))) => {
+            let page = PageDto::new(nodes, fetch_limit, |node: &NodeData| node.peer_id.clone());
+            Ok(HttpResponse::Ok().json(ApiResponse::success_with_data(page)))
+        }
+        Ok(Some(Message::LocalMessage(LocalMessage::AckError(err)))) => Ok(req_error_response(err)),
+        _ => Ok(HttpResponse::Ok().json(ApiResponse::error_default())),
+    }
+}
+
+#[get("/task/list")]
+async fn get_task_list(
+    api_module: Data<ApiModule>,
+    query: web::Query<PageQuery<u64>>,
+) -> Result<HttpResponse, Error> {
+    let task_caller = match api_module.task_caller.clone() {
+        Some(caller) => caller,
+        None => return Ok(HttpResponse::Ok().json(ApiResponse::error_default())),
+    };
+    let query = query.into_inner();
+    let fetch_limit = query.fetch_limit();
+    let res = task_caller
+        .clone()
+        .call(Message::LocalMessage(LocalMessage::ReqTaskPage(
+            query.start,
+            fetch_limit,
+        )))
+        .await;
+    match res {
+        Ok(Some(Message::LocalMessage(LocalMessage::AckTaskPage(tasks)))) => {
+            let page = PageDto::new(tasks, fetch_limit, |task: &TaskData| task.id);
+            Ok(HttpResponse::Ok().json(ApiResponse::success_with_data(page)))
+        }
+        Ok(Some(Message::LocalMessage(LocalMessage::AckError(err)))) => Ok(req_error_response(err)),
+        _ => Ok(HttpResponse::Ok().json(ApiResponse::error_default())),
+    }
+}
+
+#[get("/chain/sync_status")]
+async fn get_chain_sync_status(api_module: Data<ApiModule>) -> Result<HttpResponse, Error> {
+    let blockchain_caller = match api_module.blockchain_caller.clone() {
+        Some(caller) => caller,
+        None => return Ok(HttpResponse::Ok().json(ApiResponse::error_default())),
+    };
+    let res = blockchain_caller
+        .clone()
+        .call(Message::LocalMessage(LocalMessage::ReqBlockSyncStatus()))
+        .await;
+    match res {
+        Ok(Some(Message::LocalMessage(LocalMessage::AckBlockSyncStatus(sync_status)))) => {
+            Ok(HttpResponse::Ok().json(ApiResponse::success_with_data(sync_status)))
+        }
+        _ => Ok(HttpResponse::Ok().json(ApiResponse::error_default())),
+    }
+}
+
+#[get("/chain/finality")]
+async fn get_chain_finality(api_module: Data<ApiModule>) -> Result<HttpResponse, Error> {
+    let blockchain_caller = match api_module.blockchain_caller.clone() {
+        Some(caller) => caller,
+        None => return Ok(HttpResponse::Ok().json(ApiResponse::error_default())),
+    };
+    let res = blockchain_caller
+        .clone()
+        .call(Message::LocalMessage(LocalMessage::ReqBlockFinality()))
+        .await;
+    match res {
+        Ok(Some(Message::LocalMessage(LocalMessage::AckBlockFinality(finality_status)))) => {
+            Ok(HttpResponse::Ok().json(ApiResponse::success_with_data(finality_status)))
+        }
+        _ => Ok(HttpResponse::Ok().json(ApiResponse::error_default())),
+    }
+}
+
+#[get("/metrics/db_cache")]
+async fn get_db_cache_metrics(api_module: Data<ApiModule>) -> Result<HttpResponse, Error> {
+    match &api_module.db_cache {
+        Some(db_cache) => {
+            Ok(HttpResponse::Ok().json(ApiResponse::success_with_data(db_cache.metrics())))
+        }
+        None => Ok(HttpResponse::Ok().json(ApiResponse::error_default())),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StateProofDto {
+    trie: StateTrie,
+    keys: Vec<Vec<u8>>,
+}
+
+#[post("/state/proof")]
+async fn get_state_proof(
+    api_module: Data<ApiModule>,
+    form: web::Json<StateProofDto>,
+) -> Result<HttpResponse, Error> {
+    let blockchain_caller = match api_module.blockchain_caller.clone() {
+        Some(caller) => caller,
+        None => return Ok(HttpResponse::Ok().json(ApiResponse::error_default())),
+    };
+    let form = form.into_inner();
+    let res = blockchain_caller
+        .clone()
+        .call(Message::LocalMessage(LocalMessage::ReqStateProof(
+            form.trie, form.keys,
+        )))
+        .await;
+    match res {
+        Ok(Some(Message::LocalMessage(LocalMessage::AckStateProof(state_proof)))) => {
+            Ok(HttpResponse::Ok().json(ApiResponse::success_with_data(state_proof)))
+        }
+        Ok(Some(Message::LocalMessage(LocalMessage::AckError(err)))) => Ok(req_error_response(err)),
+        _ => Ok(HttpResponse::Ok().json(ApiResponse::error_default())),
+    }
+}
+
+#[get("/chain/state/{index}/tasks")]
+async fn get_history_tasks(
+    api_module: Data<ApiModule>,
+    index: web::Path<u64>,
+    query: web::Query<PageQuery<u64>>,
+) -> Result<HttpResponse, Error> {
+    let blockchain_caller = match api_module.blockchain_caller.clone() {
+        Some(caller) => caller,
+        None => return Ok(HttpResponse::Ok().json(ApiResponse::error_default())),
+    };
+    let query = query.into_inner();
+    let fetch_limit = query.fetch_limit();
+    let res = blockchain_caller
+        .clone()
+        .call(Message::LocalMessage(LocalMessage::ReqHistoryTasks(
+            index.into_inner(),
+            query.start,
+            fetch_limit,
+        )))
+        .await;
+    match res {
+        Ok(Some(Message::LocalMessage(LocalMessage::AckTaskPage(tasks)))) => {
+            let page = PageDto::new(tasks, fetch_limit, |task: &TaskData| task.id);
+            Ok(HttpResponse::Ok().json(ApiResponse::success_with_data(page)))
+        }
+        Ok(Some(Message::LocalMessage(LocalMessage::AckError(err)))) => Ok(req_error_response(err)),
+        _ => Ok(HttpResponse::Ok().json(ApiResponse::error_default())),
+    }
+}
+
+#[get("/chain/state/{index}/nodes")]
+async fn get_history_nodes(
+    api_module: Data<ApiModule>,
+    index: web::Path<u64>,
+    query: web::Query<PageQuery<String>>,
+) -> Result<HttpResponse, Error> {
+    let blockchain_caller = match api_module.blockchain_caller.clone() {
+        Some(caller) => caller,
+        None => return Ok(HttpResponse::Ok().json(ApiResponse::error_default())),
+    };
+    let query = query.into_inner();
+    let fetch_limit = query.fetch_limit();
+    let res = blockchain_caller
+        .clone()
+        .call(Message::LocalMessage(LocalMessage::ReqHistoryNodes(
+            index.into_inner(),
+            query.start,
+            fetch_limit,
+        )))
+        .await;
+    match res {
+        Ok(Some(Message::LocalMessage(LocalMessage::AckNodePage(nodes)))) => {
+            let page = PageDto::new(nodes, fetch_limit, |node: &NodeData| node.peer_id.clone());
+            Ok(HttpResponse::Ok().json(ApiResponse::success_with_data(page)))
+        }
+        Ok(Some(Message::LocalMessage(LocalMessage::AckError(err)))) => Ok(req_error_response(err)),
+        _ => Ok(HttpResponse::Ok().json(ApiResponse::error_default())),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BalanceDto {
+    account: String,
+    balance: u64,
+}
+
+#[get("/chain/state/{index}/balance/{account}")]
+async fn get_history_balance(
+    api_module: Data<ApiModule>,
+    path: web::Path<(u64, String)>,
+) -> Result<HttpResponse, Error> {
+    let blockchain_caller = match api_module.blockchain_caller.clone() {
+        Some(caller) => caller,
+        None => return Ok(HttpResponse::Ok().json(ApiResponse::error_default())),
+    };
+    let (index, account) = path.into_inner();
+    // accounts are keyed by the cbor bytes of the principal text
+    let account_bytes = match serde_cbor::to_vec(&account) {
+        Ok(bytes) => bytes,
+        Err(_) => return Ok(HttpResponse::Ok().json(ApiResponse::error_default())),
+    };
+    let res = blockchain_caller
+        .clone()
+        .call(Message::LocalMessage(LocalMessage::ReqHistoryBalance(
+            index,
+            account_bytes,
+        )))
+        .await;
+    match res {
+        Ok(Some(Message::LocalMessage(LocalMessage::AckHistoryBalance(balance)))) => Ok(
+            HttpResponse::Ok().json(ApiResponse::success_with_data(BalanceDto {
+                account,
+                balance,
+            })),
+        ),
+        Ok(Some(Message::LocalMessage(LocalMessage::AckError(err)))) => Ok(req_error_response(err)),
+        _ => Ok(HttpResponse::Ok().json(ApiResponse::error_default())),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RewardDto {
+    account: String,
+    amount: u64,
+}
+
+#[get("/chain/rewards/{index}")]
+async fn get_block_rewards(
+    api_module: Data<ApiModule>,
+    index: web::Path<u64>,
+) -> Result<HttpResponse, Error> {
+    let blockchain_caller = match api_module.blockchain_caller.clone() {
+        Some(caller) => caller,
+        None => return Ok(HttpResponse::Ok().json(ApiResponse::error_default())),
+    };
+    let res = blockchain_caller
+        .clone()
+        .call(Message::LocalMessage(LocalMessage::ReqBlockRewards(
+            index.into_inner(),
+        )))
+        .await;
+    match res {
+        Ok(Some(Message::LocalMessage(LocalMessage::AckBlockRewards(rewards)))) => {
+            let rewards: Vec<RewardDto> = rewards
+                .into_iter()
+                .map(|reward| RewardDto {
+                    account: serde_cbor::from_slice(&reward.account).unwrap_or_default(),
+                    amount: reward.amount,
+                })
+                .collect();
+            Ok(HttpResponse::Ok().json(ApiResponse::success_with_data(rewards)))
+        }
+        Ok(Some(Message::LocalMessage(LocalMessage::AckError(err)))) => Ok(req_error_response(err)),
+        _ => Ok(HttpResponse::Ok().json(ApiResponse::error_default())),
+    }
+}
+
+/// `req_error_response` the response of a request the module answered with an error.
+fn req_error_response(err: ReqError) -> HttpResponse {
+    match err {
+        ReqError::NotFound(msg) => {
+            HttpResponse::NotFound().json(ApiResponse::error("404".to_owned(), msg))
+        }
+        ReqError::Gone(msg) => HttpResponse::Gone().json(ApiResponse::error("410".to_owned(), msg)),
+        ReqError::Invalid(msg) => {
+            HttpResponse::BadRequest().json(ApiResponse::error("400".to_owned(), msg))
+        }
+        ReqError::Internal(msg) => {
+            HttpResponse::InternalServerError().json(ApiResponse::error("500".to_owned(), msg))
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -128,6 +461,19 @@ where
     data: Option<T>,
 }
 
+impl<T> ApiResponse<T>
+where
+    T: Serialize,
+{
+    pub fn success_with_data(data: T) -> Self {
+        ApiResponse {
+            code: "200".to_owned(),
+            msg: "success".to_owned(),
+            data: Some(data),
+        }
+    }
+}
+
 impl ApiResponse<()> {
     pub fn success() -> Self {
         ApiResponse {
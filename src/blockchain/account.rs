@@ -0,0 +1,135 @@
+use std::sync::Arc;
+
+use ethereum_types::H256;
+use hash_db::Hasher;
+use keccak_hasher::KeccakHasher;
+use kvdb::KeyValueDB;
+use parity_scale_codec::{Decode, Encode};
+
+use super::{db::Reward, error::Result};
+use crate::database::{self, AppDB};
+
+/// `AccountDB` keeps the balance of every account keyed by the account key, and the reward
+/// history keyed by the block index followed by the account key.
+#[derive(Clone)]
+pub struct AccountDB {
+    pub db: Arc<dyn KeyValueDB>,
+    pub account_db: AppDB,
+    pub reward_db: AppDB,
+}
+
+impl AccountDB {
+    pub fn new(db: Arc<dyn KeyValueDB>, account_root: H256, reward_root: H256) -> Result<Self> {
+        let account_db = AppDB::new(
+            db.clone(),
+            database::db::COL_ACCOUNT,
+            account_root.to_fixed_bytes(),
+        )?;
+        let reward_db = AppDB::new(
+            db.clone(),
+            database::db::COL_ACCOUNT,
+            reward_root.to_fixed_bytes(),
+        )?;
+
+        Ok(AccountDB {
+            db,
+            account_db,
+            reward_db,
+        })
+    }
+
+    /// `set_inline_threshold` set the inline value threshold of the chain on the state tries.
+    pub fn set_inline_threshold(&mut self, threshold: u32) -> Result<()> {
+        self.account_db.set_inline_threshold(threshold)?;
+        self.reward_db.set_inline_threshold(threshold)?;
+        Ok(())
+    }
+
+    pub fn get_balance(&self, account: &[u8]) -> Result<u64> {
+        match self.account_db.get(&account_key(account))? {
+            Some(balance) => Ok(u64::decode(&mut balance.as_slice())?),
+            None => Ok(0),
+        }
+    }
+
+    pub fn add_balance(&mut self, account: &[u8], amount: u64) -> Result<()> {
+        let balance = self.get_balance(account)?;
+        let balance = match balance.checked_add(amount) {
+            Some(b) => b,
+            None => return Err("account balance overflow".to_string().into()),
+        };
+        self.account_db
+            .insert(&account_key(account), &balance.encode())?;
+        Ok(())
+    }
+
+    /// `insert_reward` record the reward of the account in the block, then add it to the balance.
+    pub fn insert_reward(&mut self, index: u64, reward: &Reward) -> Result<()> {
+        self.reward_db
+            .insert(&reward_key(index, &reward.account), &reward.amount.encode())?;
+        self.add_balance(&reward.account, reward.amount)
+    }
+
+    pub fn get_reward(&self, index: u64, account: &[u8]) -> Result<u64> {
+        match self.reward_db.get(&reward_key(index, account))? {
+            Some(amount) => Ok(u64::decode(&mut amount.as_slice())?),
+            None => Ok(0),
+        }
+    }
+
+    pub fn commit(&mut self) -> Result<()> {
+        self.account_db.commit()?;
+        self.reward_db.commit()?;
+        Ok(())
+    }
+
+    pub fn get_root(&self) -> H256 {
+        H256(self.account_db.get_root())
+    }
+
+    pub fn get_reward_root(&self) -> H256 {
+        H256(self.reward_db.get_root())
+    }
+}
+
+/// `account_key` the trie key of the account, the keccak hash of the account bytes. Account
+/// bytes have no bound length, a trie key must stay short enough for the trie nodes.
+pub fn account_key(account: &[u8]) -> [u8; 32] {
+    KeccakHasher::hash(account)
+}
+
+fn reward_key(index: u64, account: &[u8]) -> Vec<u8> {
+    [&index.to_be_bytes()[..], &account_key(account)].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A self-authenticating principal has the longest principal text, 63 characters.
+    const LONG_PRINCIPAL: &str = "hpikg-6exdt-jn33w-ndty3-fc7jc-tl2lr-buih3-cs3y7-tftkp-sfp62-gqe";
+
+    #[test]
+    fn test_long_account() {
+        let db = database::open_memory_database();
+        let mut account_db = AccountDB::new(db.clone(), H256::zero(), H256::zero()).unwrap();
+        let account = serde_cbor::to_vec(&LONG_PRINCIPAL).unwrap();
+        account_db.add_balance(&account, 10).unwrap();
+        account_db
+            .insert_reward(
+                7,
+                &Reward {
+                    account: account.clone(),
+                    amount: 5,
+                },
+            )
+            .unwrap();
+        account_db.commit().unwrap();
+
+        let account_db =
+            AccountDB::new(db, account_db.get_root(), account_db.get_reward_root()).unwrap();
+        assert_eq!(account_db.get_balance(&account).unwrap(), 15);
+        assert_eq!(account_db.get_reward(7, &account).unwrap(), 5);
+        assert_eq!(account_db.get_reward(8, &account).unwrap(), 0);
+    }
+}
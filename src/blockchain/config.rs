@@ -0,0 +1,6 @@
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct BlockchainConfig {
+    pub genesis_path: String,
+}
@@ -4,13 +4,23 @@ use ethereum_types::H256;
 use futures::future::ok;
 use hash_db::Hasher;
 use keccak_hasher::KeccakHasher;
-use kvdb::KeyValueDB;
+use kvdb::{DBTransaction, KeyValueDB};
+use parity_scale_codec::{Decode, Encode};
 use serde::{Deserialize, Serialize};
 
 use super::error::Result;
-use crate::database::{self, data_types::TaskType, AppDB};
+use crate::database::{
+    self,
+    data_types::{NodeType, TaskType},
+    journal,
+    overlay::OverlayDB,
+    AppDB,
+};
 
 pub const KEY_LAST_HASH: &[u8; 11] = b"latest_hash";
+pub const KEY_FINALIZED_HEIGHT: &[u8; 16] = b"finalized_height";
+/// The genesis hash of a chain restored from a snapshot, its genesis block is not stored.
+pub const KEY_GENESIS_HASH: &[u8; 12] = b"genesis_hash";
 
 #[derive(Clone)]
 pub struct BlockchainDB {
@@ -41,38 +51,138 @@ impl BlockchainDB {
         })
     }
 
+    /// `insert_block` store the block by its hash, the block is not on the canonical chain
+    /// until `set_canonical_branch` links it.
     pub fn insert_block(&mut self, block: Block) -> Result<()> {
-        let header_index_bytes = serde_cbor::to_vec(&block.header.index)?;
-
-        let header_bytes = serde_cbor::to_vec(&block.header)?;
-        let body_bytes = serde_cbor::to_vec(&block.body)?;
-
-        let block_bytes = serde_cbor::to_vec(&block)?;
+        self.commit_block(&block, None, None)
+    }
 
-        let hash = KeccakHasher::hash(&block_bytes);
+    /// `commit_block` write the block, the trie nodes buffered by its state with their journal,
+    /// and the canonical links of the head update in one transaction, a crash never leaves the
+    /// state roots and the blocks out of sync.
+    pub fn commit_block(
+        &mut self,
+        block: &Block,
+        state: Option<&OverlayDB>,
+        head_update: Option<&HeadUpdate>,
+    ) -> Result<()> {
+        let mut header_db = self.header_db.clone();
+        let mut body_db = self.body_db.clone();
+        let hash = block.hash()?;
+        header_db.insert(hash.as_bytes(), &serde_cbor::to_vec(&block.header)?)?;
+        body_db.insert(hash.as_bytes(), &serde_cbor::to_vec(&block.body)?)?;
 
         let mut tx = self.db.transaction();
-        tx.put(database::db::COL_EXTRA, &header_index_bytes, &hash);
-        tx.put(database::db::COL_EXTRA, KEY_LAST_HASH, &hash);
+        if let Some(state) = state {
+            let node_journal = journal::take_pending(state)?;
+            state.drain_into(&mut tx);
+            if !node_journal.is_empty() {
+                journal::add_refs(self.db.as_ref(), &mut tx, &node_journal)?;
+                tx.put(
+                    database::db::COL_EXTRA,
+                    &journal::journal_key(block.header.index, hash.as_bytes()),
+                    &node_journal.encode(),
+                );
+            }
+        }
+        header_db.drain_into(&mut tx)?;
+        body_db.drain_into(&mut tx)?;
+        tx.put(
+            database::db::COL_BLOCK_HEADERS,
+            database::KEY_ROOT,
+            &header_db.get_root(),
+        );
+        tx.put(
+            database::db::COL_BLOCK_BODIES,
+            database::KEY_ROOT,
+            &body_db.get_root(),
+        );
+        if let Some(head_update) = head_update {
+            link_canonical(&mut tx, head_update)?;
+        }
         self.db.write(tx)?;
 
-        self.header_db.insert(&hash, &header_bytes)?;
-        self.body_db.insert(&hash, &body_bytes)?;
+        self.header_db = header_db;
+        self.body_db = body_db;
+        Ok(())
+    }
 
+    /// `set_canonical_branch` link the branch blocks by index and make the last one the head,
+    /// the indexes above the new head up to the old head index are unlinked.
+    pub fn set_canonical_branch(
+        &mut self,
+        branch: &[Block],
+        old_head_index: Option<u64>,
+    ) -> Result<()> {
+        let mut tx = self.db.transaction();
+        link_canonical(
+            &mut tx,
+            &HeadUpdate {
+                branch: branch.to_vec(),
+                old_head_index,
+            },
+        )?;
+        self.db.write(tx)?;
         Ok(())
     }
 
-    pub fn get_block_by_index(&self, index: u64) -> Result<Block> {
+    pub fn has_block(&self, hash: H256) -> Result<bool> {
+        Ok(self.header_db.get(hash.as_bytes())?.is_some())
+    }
+
+    /// `get_canonical_hash` the hash of the block at the index on the canonical chain.
+    pub fn get_canonical_hash(&self, index: u64) -> Result<Option<H256>> {
         let hash = self.db.get(
             database::db::COL_EXTRA,
             serde_cbor::to_vec(&index)?.as_slice(),
         )?;
-        let hash = match hash {
+        Ok(hash.map(|h| H256::from_slice(h.as_slice())))
+    }
+
+    /// `get_genesis_hash` the hash of the stored genesis block, or the genesis hash recorded by
+    /// the snapshot restore.
+    pub fn get_genesis_hash(&self) -> Result<Option<H256>> {
+        if let Some(hash) = self.get_canonical_hash(0)? {
+            return Ok(Some(hash));
+        }
+        let hash = self.db.get(database::db::COL_EXTRA, KEY_GENESIS_HASH)?;
+        Ok(hash.map(|h| H256::from_slice(h.as_slice())))
+    }
+
+    pub fn get_block_by_index(&self, index: u64) -> Result<Block> {
+        let hash = match self.get_canonical_hash(index)? {
             Some(h) => h,
             None => return Err("block index not found".to_string().into()),
         };
 
-        self.get_block_by_hash(H256::from_slice(hash.as_slice()))
+        self.get_block_by_hash(hash)
+    }
+
+    /// `get_latest_block` get the head block of the chain, return none if no block was stored.
+    pub fn get_latest_block(&self) -> Result<Option<Block>> {
+        match get_latest_hash(self.db.clone())? {
+            Some(hash) => Ok(Some(self.get_block_by_hash(hash)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// `get_finalized_height` the number of finalized blocks, zero if no block is final.
+    pub fn get_finalized_height(&self) -> Result<u64> {
+        match self.db.get(database::db::COL_EXTRA, KEY_FINALIZED_HEIGHT)? {
+            Some(height) => Ok(serde_cbor::from_slice(&height)?),
+            None => Ok(0),
+        }
+    }
+
+    pub fn set_finalized_height(&mut self, height: u64) -> Result<()> {
+        let mut tx = self.db.transaction();
+        tx.put(
+            database::db::COL_EXTRA,
+            KEY_FINALIZED_HEIGHT,
+            &serde_cbor::to_vec(&height)?,
+        );
+        self.db.write(tx)?;
+        Ok(())
     }
 
     pub fn get_block_by_hash(&self, hash: H256) -> Result<Block> {
@@ -95,6 +205,61 @@ impl BlockchainDB {
     }
 }
 
+/// `HeadUpdate` the branch a block commit links on the canonical chain, the old head index is
+/// set when the branch replaces blocks of the old canonical chain.
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct HeadUpdate {
+    pub branch: Vec<Block>,
+    pub old_head_index: Option<u64>,
+}
+
+/// `link_canonical` put the index links of the branch, the head pointer, and the state roots of
+/// the new head into the transaction.
+fn link_canonical(tx: &mut DBTransaction, head_update: &HeadUpdate) -> Result<()> {
+    let head = match head_update.branch.last() {
+        Some(head) => head,
+        None => return Ok(()),
+    };
+
+    for block in &head_update.branch {
+        tx.put(
+            database::db::COL_EXTRA,
+            &serde_cbor::to_vec(&block.header.index)?,
+            block.hash()?.as_bytes(),
+        );
+    }
+    if let Some(old_head_index) = head_update.old_head_index {
+        for index in head.header.index + 1..=old_head_index {
+            tx.delete(database::db::COL_EXTRA, &serde_cbor::to_vec(&index)?);
+        }
+    }
+    tx.put(
+        database::db::COL_EXTRA,
+        KEY_LAST_HASH,
+        head.hash()?.as_bytes(),
+    );
+
+    // The state tries are reopened at these roots after a restart.
+    let header = &head.header;
+    for (column, root) in [
+        (database::db::COL_ACCOUNT, header.account_root),
+        (database::db::COL_TASK_LIST, header.task_root),
+        (
+            database::db::COL_TASK_OPERATIONS,
+            header.task_operation_root,
+        ),
+        (database::db::COL_TASK_RESULT, header.task_result_root),
+        (database::db::COL_NODE_LIST, header.node_root),
+        (
+            database::db::COL_NODE_LIST_ACTIVATED,
+            header.node_activation_root,
+        ),
+    ] {
+        tx.put(column, database::KEY_ROOT, root.as_bytes());
+    }
+    Ok(())
+}
+
 pub fn get_latest_hash(db: Arc<dyn KeyValueDB>) -> Result<Option<H256>> {
     match db.get(database::db::COL_EXTRA, KEY_LAST_HASH)? {
         Some(h) => Ok(Some(H256::from_slice(h.as_slice()))),
@@ -102,14 +267,28 @@ pub fn get_latest_hash(db: Arc<dyn KeyValueDB>) -> Result<Option<H256>> {
     }
 }
 
-#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize, Default)]
+/// `get_latest_header` get the header of the head block, modules reopen their state roots from it.
+pub fn get_latest_header(db: Arc<dyn KeyValueDB>) -> Result<Option<Header>> {
+    let blockchain_db = BlockchainDB::new(db)?;
+    Ok(blockchain_db.get_latest_block()?.map(|block| block.header))
+}
+
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize, Encode, Decode, Default)]
 pub struct Block {
     pub header: Header,
     pub body: Body,
 }
 
-#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize, Default)]
+impl Block {
+    /// `hash` is the keccak hash of the SCALE encoded block.
+    pub fn hash(&self) -> Result<H256> {
+        Ok(H256(KeccakHasher::hash(&self.encode())))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize, Encode, Decode, Default)]
 pub struct Header {
+    pub chain_id: String,
     pub index: u64,
     pub previous_hash: H256,
     pub account_root: H256,
@@ -129,7 +308,7 @@ pub struct Header {
     pub signature: Vec<u8>,
 }
 
-#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize, Default)]
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize, Encode, Decode, Default)]
 pub struct Body {
     pub reward: Vec<Reward>,
     pub tasks: Vec<TaskOperation>,
@@ -148,7 +327,7 @@ impl Body {
     }
 }
 
-#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize, Encode, Decode)]
 pub enum TaskOperationType {
     Add,
     Remove,
@@ -156,7 +335,7 @@ pub enum TaskOperationType {
     Enable,
 }
 
-#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize, Encode, Decode)]
 pub struct TaskOperation {
     pub id: u64,
     pub operation: TaskOperationType,
@@ -166,35 +345,148 @@ pub struct TaskOperation {
     pub reward_weight: u64,
 }
 
-#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize, Encode, Decode)]
 pub struct TaskResult {
     pub id: u64,
+    pub peer_id: String,
     pub timestamp: u64,
     pub result: Vec<u8>,
 }
 
-#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize, Encode, Decode)]
 pub enum ActivationOperation {
     Activate,
     Deactivate,
 }
 
-#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize, Encode, Decode)]
 pub struct NodeActivation {
     pub operation: ActivationOperation,
     pub peer_id: String,
     pub account: Vec<u8>,
     pub pub_key: Vec<u8>,
+    pub node_type: NodeType,
 }
 
-#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize, Encode, Decode)]
 pub struct NeedSignData<T> {
     pub data: T,
     pub signature: Vec<u8>,
 }
 
-#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize, Encode, Decode)]
 pub struct Reward {
     pub account: Vec<u8>,
     pub amount: u64,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::data_types::NodeData;
+
+    fn child_block(parent: &Block, version: u64) -> Block {
+        let mut block = Block::default();
+        block.header.index = parent.header.index + 1;
+        block.header.previous_hash = parent.hash().unwrap();
+        block.header.version = version;
+        block
+    }
+
+    #[test]
+    fn test_scale_encoding() {
+        let mut block = Block::default();
+        block.body.reward.push(Reward {
+            account: b"account".to_vec(),
+            amount: 10,
+        });
+        let block_bytes = block.encode();
+        assert_eq!(Block::decode(&mut &block_bytes[..]).unwrap(), block);
+        assert_eq!(
+            block.hash().unwrap(),
+            H256(KeccakHasher::hash(&block_bytes))
+        );
+
+        // the stake maps encode the same whatever the insertion order
+        let mut node_one = NodeData::default();
+        node_one.worker_stake_amount.insert("a".to_string(), 1);
+        node_one.worker_stake_amount.insert("b".to_string(), 2);
+        let mut node_two = NodeData::default();
+        node_two.worker_stake_amount.insert("b".to_string(), 2);
+        node_two.worker_stake_amount.insert("a".to_string(), 1);
+        assert_eq!(node_one.encode(), node_two.encode());
+    }
+
+    #[test]
+    fn test_set_canonical_branch() {
+        let db = database::open_memory_database();
+        let mut blockchain_db = BlockchainDB::new(db).unwrap();
+
+        let genesis = Block::default();
+        let block_one = child_block(&genesis, 0);
+        let block_two = child_block(&block_one, 0);
+        let fork_one = child_block(&genesis, 1);
+        for block in [&genesis, &block_one, &block_two, &fork_one] {
+            blockchain_db.insert_block(block.clone()).unwrap();
+        }
+        blockchain_db
+            .set_canonical_branch(&[genesis.clone(), block_one, block_two.clone()], None)
+            .unwrap();
+        assert_eq!(blockchain_db.get_latest_block().unwrap(), Some(block_two));
+
+        // the side block is stored but not linked until the fork wins
+        assert!(blockchain_db.has_block(fork_one.hash().unwrap()).unwrap());
+        blockchain_db
+            .set_canonical_branch(&[fork_one.clone()], Some(2))
+            .unwrap();
+        assert_eq!(
+            blockchain_db.get_block_by_index(1).unwrap(),
+            fork_one.clone()
+        );
+        assert!(blockchain_db.get_block_by_index(2).is_err());
+        assert_eq!(blockchain_db.get_latest_block().unwrap(), Some(fork_one));
+    }
+
+    #[test]
+    fn test_commit_block_reopen() {
+        let db = database::open_memory_database();
+        let mut blockchain_db = BlockchainDB::new(db.clone()).unwrap();
+
+        let state = OverlayDB::new(db.clone());
+        let mut account_db =
+            AppDB::new(state.clone(), database::db::COL_ACCOUNT, [0u8; 32]).unwrap();
+        account_db.insert(b"account", b"balance").unwrap();
+        account_db.commit().unwrap();
+        let mut genesis = Block::default();
+        genesis.header.account_root = H256(account_db.get_root());
+        // nothing reaches the database before the commit
+        assert!(
+            AppDB::new(db.clone(), database::db::COL_ACCOUNT, account_db.get_root())
+                .unwrap()
+                .get(b"account")
+                .is_err()
+        );
+        let head_update = HeadUpdate {
+            branch: vec![genesis.clone()],
+            old_head_index: None,
+        };
+        blockchain_db
+            .commit_block(&genesis, Some(&state), Some(&head_update))
+            .unwrap();
+
+        // reopen as after a restart
+        let blockchain_db = BlockchainDB::new(db.clone()).unwrap();
+        assert_eq!(
+            blockchain_db.get_latest_block().unwrap(),
+            Some(genesis.clone())
+        );
+        let root = database::get_root(&db, database::db::COL_ACCOUNT).unwrap();
+        assert_eq!(root, genesis.header.account_root);
+        let account_db = AppDB::new(db, database::db::COL_ACCOUNT, root.to_fixed_bytes()).unwrap();
+        assert_eq!(
+            account_db.get(b"account").unwrap(),
+            Some(b"balance".to_vec())
+        );
+    }
+}
@@ -5,6 +5,10 @@ use std::{fmt, result};
 use serde::Serialize;
 
 use crate::database;
+use crate::ic::error::ICError;
+use crate::message::MessageError;
+use crate::node::error::NodeError;
+use crate::task::error::TaskError;
 
 pub type Result<T> = result::Result<T, BlockchainError>;
 
@@ -37,3 +41,10 @@ quick_from!(String);
 quick_from!(IoError);
 quick_from!(database::error::DatabaseError);
 quick_from!(serde_cbor::Error);
+quick_from!(parity_scale_codec::Error);
+quick_from!(serde_json::Error);
+quick_from!(toml::de::Error);
+quick_from!(ICError);
+quick_from!(MessageError);
+quick_from!(NodeError);
+quick_from!(TaskError);
@@ -0,0 +1,379 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use ethereum_types::H256;
+use libp2p::identity::PublicKey;
+use parity_scale_codec::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    message::Message,
+    network::{
+        topics::{SubTopics, TopicMessage, Topics},
+        NetworkMessage,
+    },
+    node::db::NodeDB,
+};
+
+use super::{
+    db::{Block, NeedSignData},
+    error::Result,
+    BlockchainModule,
+};
+
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Hash, Deserialize, Serialize, Encode, Decode)]
+pub enum VoteType {
+    Prevote,
+    Precommit,
+}
+
+/// `Vote` a verify node votes for the block hash at the index, signed by its peer key.
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize, Encode, Decode)]
+pub struct Vote {
+    pub vote_type: VoteType,
+    pub index: u64,
+    pub block_hash: H256,
+    pub peer_id: String,
+    pub pub_key: Vec<u8>,
+}
+
+/// `FinalityStatus` the finalized height is the number of finalized blocks.
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize)]
+pub struct FinalityStatus {
+    pub finalized_height: u64,
+    pub current_height: u64,
+}
+
+/// Votes more than this number of blocks above the current block are dropped.
+pub const MAX_VOTE_AHEAD_BLOCKS: u64 = 64;
+/// Max number of votes kept for one index, over all vote types and block hashes.
+pub const MAX_VOTES_PER_INDEX: usize = 1024;
+
+/// `VoteCollector` the peers voted for every block hash, votes below the finalized height
+/// are dropped.
+#[derive(Default)]
+pub struct VoteCollector {
+    votes: BTreeMap<u64, HashMap<(VoteType, H256), BTreeSet<String>>>,
+}
+
+impl VoteCollector {
+    /// `insert` keep the vote, return false if the index already has `MAX_VOTES_PER_INDEX` votes.
+    pub fn insert(&mut self, vote: &Vote) -> bool {
+        let index_votes = self.votes.entry(vote.index).or_insert_with(HashMap::new);
+        let count: usize = index_votes.values().map(|peers| peers.len()).sum();
+        let peers = index_votes
+            .entry((vote.vote_type, vote.block_hash))
+            .or_insert_with(BTreeSet::new);
+        if peers.contains(&vote.peer_id) {
+            return true;
+        }
+        if count >= MAX_VOTES_PER_INDEX {
+            return false;
+        }
+        peers.insert(vote.peer_id.clone());
+        true
+    }
+
+    fn peers(&self, vote_type: VoteType, index: u64, hash: H256) -> Option<&BTreeSet<String>> {
+        self.votes
+            .get(&index)
+            .and_then(|index_votes| index_votes.get(&(vote_type, hash)))
+    }
+
+    pub fn has_voted(&self, vote_type: VoteType, index: u64, hash: H256, peer_id: &str) -> bool {
+        match self.peers(vote_type, index, hash) {
+            Some(peers) => peers.contains(peer_id),
+            None => false,
+        }
+    }
+
+    /// `retain_voters` drop the votes at the index from peers out of the voter set.
+    pub fn retain_voters(&mut self, index: u64, voters: &BTreeMap<String, u128>) {
+        if let Some(index_votes) = self.votes.get_mut(&index) {
+            for peers in index_votes.values_mut() {
+                peers.retain(|peer_id| voters.contains_key(peer_id));
+            }
+            index_votes.retain(|_, peers| !peers.is_empty());
+        }
+    }
+
+    /// `weight` the voting rights of the voters who voted for the block hash.
+    pub fn weight(
+        &self,
+        vote_type: VoteType,
+        index: u64,
+        hash: H256,
+        voters: &BTreeMap<String, u128>,
+    ) -> u128 {
+        match self.peers(vote_type, index, hash) {
+            Some(peers) => peers.iter().filter_map(|p| voters.get(p)).sum(),
+            None => 0,
+        }
+    }
+
+    pub fn prune(&mut self, finalized_height: u64) {
+        self.votes = self.votes.split_off(&finalized_height);
+    }
+}
+
+/// `has_quorum` more than two thirds of the total voting rights.
+pub fn has_quorum(weight: u128, total_weight: u128) -> bool {
+    total_weight > 0 && weight * 3 > total_weight * 2
+}
+
+/// `verify_vote` check the vote was signed by the key of the voting peer.
+pub fn verify_vote(vote: &NeedSignData<Vote>) -> Result<()> {
+    let public_key = match PublicKey::from_protobuf_encoding(&vote.data.pub_key) {
+        Ok(k) => k,
+        Err(_) => return Err("invalid vote public key".to_string().into()),
+    };
+    if public_key.to_peer_id().to_base58() != vote.data.peer_id {
+        return Err("vote peer id mismatch".to_string().into());
+    }
+
+    let data_bytes = vote.data.encode();
+    if !public_key.verify(&data_bytes, &vote.signature) {
+        return Err("invalid vote signature".to_string().into());
+    }
+    Ok(())
+}
+
+impl BlockchainModule {
+    pub fn finality_status(&self) -> FinalityStatus {
+        FinalityStatus {
+            finalized_height: self.finalized_height,
+            current_height: self.current_block.header.index,
+        }
+    }
+
+    /// `check_final` return an error if the block at the index is not finalized yet.
+    pub fn check_final(&self, index: u64) -> Result<()> {
+        if index >= self.finalized_height {
+            return Err(format!("block {} is not finalized", index).into());
+        }
+        Ok(())
+    }
+
+    fn local_peer_id(&self) -> String {
+        PublicKey::Ed25519(self.local_key.public())
+            .to_peer_id()
+            .to_base58()
+    }
+
+    /// `voters_of` the voter set recorded in the state of the block.
+    fn voters_of(&self, block: &Block) -> Result<BTreeMap<String, u128>> {
+        let node_db = NodeDB::with_roots(
+            self.db.db.clone(),
+            block.header.node_root,
+            block.header.node_activation_root,
+        )?;
+        Ok(node_db.get_voters()?)
+    }
+
+    /// `vote_block` prevote the block if this node is a voter, then check the votes of the block.
+    pub async fn vote_block(&mut self, block: &Block) -> Result<()> {
+        if block.header.index < self.finalized_height {
+            return Ok(());
+        }
+        let hash = block.hash()?;
+        // Only vote the canonical chain, a voter never votes two blocks of the same index.
+        if self.db.get_canonical_hash(block.header.index)? != Some(hash) {
+            return Ok(());
+        }
+        let voters = self.voters_of(block)?;
+        self.votes.retain_voters(block.header.index, &voters);
+        if voters.contains_key(&self.local_peer_id()) {
+            self.send_vote(VoteType::Prevote, block.header.index, hash)
+                .await?;
+        }
+        self.check_votes(block.header.index, hash).await
+    }
+
+    async fn send_vote(&mut self, vote_type: VoteType, index: u64, block_hash: H256) -> Result<()> {
+        let vote = Vote {
+            vote_type,
+            index,
+            block_hash,
+            peer_id: self.local_peer_id(),
+            pub_key: PublicKey::Ed25519(self.local_key.public()).to_protobuf_encoding(),
+        };
+        let signature = self.local_key.sign(&vote.encode());
+        let vote = NeedSignData {
+            data: vote,
+            signature,
+        };
+        self.votes.insert(&vote.data);
+
+        let topic_msg = TopicMessage {
+            sub_topic: SubTopics::Vote,
+            data: serde_cbor::to_vec(&vote)?,
+        };
+        self.network_caller
+            .notify(Message::NetworkMessage(NetworkMessage {
+                peer_id: None,
+                topic: Topics::Vote,
+                message: serde_cbor::to_vec(&topic_msg)?,
+            }))
+            .await?;
+        Ok(())
+    }
+
+    /// `deal_vote_message` collect the vote of a peer. Votes for blocks not imported yet are kept
+    /// until the block comes, if they are not too far above the current block.
+    pub async fn deal_vote_message(&mut self, msg: &TopicMessage) -> Result<()> {
+        let vote: NeedSignData<Vote> = serde_cbor::from_slice(&msg.data)?;
+        let current_index = self.current_block.header.index;
+        if vote.data.index < self.finalized_height
+            || vote.data.index > current_index.saturating_add(MAX_VOTE_AHEAD_BLOCKS)
+        {
+            return Ok(());
+        }
+        verify_vote(&vote)?;
+        let known = vote.data.index < current_index;
+        if known {
+            let block = self.db.get_block_by_index(vote.data.index)?;
+            if !self.voters_of(&block)?.contains_key(&vote.data.peer_id) {
+                return Err(format!("{} is not a voter", vote.data.peer_id).into());
+            }
+        }
+        if !self.votes.insert(&vote.data) {
+            return Err(format!("too many votes at index {}", vote.data.index).into());
+        }
+        if known {
+            self.check_votes(vote.data.index, vote.data.block_hash)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// `check_votes` precommit the block when its prevotes reach the quorum,
+    /// and finalize it when its precommits reach the quorum.
+    async fn check_votes(&mut self, index: u64, hash: H256) -> Result<()> {
+        let block = self.db.get_block_by_index(index)?;
+        if block.hash()? != hash {
+            return Ok(());
+        }
+        let voters = self.voters_of(&block)?;
+        let total_weight: u128 = voters.values().sum();
+        let peer_id = self.local_peer_id();
+
+        let prevote_weight = self.votes.weight(VoteType::Prevote, index, hash, &voters);
+        if has_quorum(prevote_weight, total_weight)
+            && voters.contains_key(&peer_id)
+            && !self
+                .votes
+                .has_voted(VoteType::Precommit, index, hash, &peer_id)
+        {
+            self.send_vote(VoteType::Precommit, index, hash).await?;
+        }
+
+        let precommit_weight = self.votes.weight(VoteType::Precommit, index, hash, &voters);
+        if has_quorum(precommit_weight, total_weight) {
+            self.finalize(index)?;
+        }
+        Ok(())
+    }
+
+    /// `finalize` mark the block and all its ancestors final.
+    fn finalize(&mut self, index: u64) -> Result<()> {
+        if index < self.finalized_height {
+            return Ok(());
+        }
+        self.finalized_height = index + 1;
+        self.db.set_finalized_height(self.finalized_height)?;
+        self.votes.prune(self.finalized_height);
+        log::info!("finalize block {}", index);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use libp2p::identity::Keypair;
+
+    #[test]
+    fn test_quorum() {
+        assert!(!has_quorum(0, 0));
+        assert!(has_quorum(1, 1));
+        assert!(!has_quorum(2, 3));
+        assert!(has_quorum(3, 4));
+    }
+
+    #[test]
+    fn test_vote_weight() {
+        let mut voters = BTreeMap::new();
+        voters.insert("one".to_owned(), 2);
+        voters.insert("two".to_owned(), 1);
+        let mut votes = VoteCollector::default();
+        for peer_id in ["one", "one", "three"] {
+            votes.insert(&Vote {
+                vote_type: VoteType::Prevote,
+                index: 1,
+                block_hash: H256::zero(),
+                peer_id: peer_id.to_owned(),
+                pub_key: vec![],
+            });
+        }
+
+        assert_eq!(votes.weight(VoteType::Prevote, 1, H256::zero(), &voters), 2);
+        assert_eq!(
+            votes.weight(VoteType::Precommit, 1, H256::zero(), &voters),
+            0
+        );
+        votes.retain_voters(1, &voters);
+        assert!(!votes.has_voted(VoteType::Prevote, 1, H256::zero(), "three"));
+        assert!(votes.has_voted(VoteType::Prevote, 1, H256::zero(), "one"));
+        votes.prune(2);
+        assert_eq!(votes.weight(VoteType::Prevote, 1, H256::zero(), &voters), 0);
+    }
+
+    #[test]
+    fn test_votes_per_index() {
+        let mut votes = VoteCollector::default();
+        let mut vote = Vote {
+            vote_type: VoteType::Prevote,
+            index: 1,
+            block_hash: H256::zero(),
+            peer_id: String::new(),
+            pub_key: vec![],
+        };
+        for i in 0..MAX_VOTES_PER_INDEX {
+            vote.peer_id = i.to_string();
+            vote.vote_type = if i % 2 == 0 {
+                VoteType::Prevote
+            } else {
+                VoteType::Precommit
+            };
+            assert!(votes.insert(&vote));
+        }
+        vote.peer_id = "full".to_owned();
+        assert!(!votes.insert(&vote));
+        vote.peer_id = "0".to_owned();
+        vote.vote_type = VoteType::Prevote;
+        assert!(votes.insert(&vote), "a known vote is kept");
+        vote.index = 2;
+        assert!(votes.insert(&vote), "other indexes have their own room");
+    }
+
+    #[test]
+    fn test_verify_vote() {
+        let key = Keypair::generate_ed25519();
+        let vote = Vote {
+            vote_type: VoteType::Precommit,
+            index: 1,
+            block_hash: H256::repeat_byte(1),
+            peer_id: key.public().to_peer_id().to_base58(),
+            pub_key: key.public().to_protobuf_encoding(),
+        };
+        let signature = key.sign(&vote.encode()).unwrap();
+        let mut vote = NeedSignData {
+            data: vote,
+            signature,
+        };
+        assert!(verify_vote(&vote).is_ok());
+
+        vote.data.index = 2;
+        assert!(verify_vote(&vote).is_err(), "tampered vote");
+    }
+}
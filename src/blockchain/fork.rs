@@ -0,0 +1,96 @@
+use crate::message::{LocalMessage, Message};
+
+use super::{
+    db::{Block, HeadUpdate},
+    error::Result,
+    state::StateRoots,
+    BlockchainModule,
+};
+
+impl BlockchainModule {
+    /// `fork_choice` decide how the block changes the canonical chain before it's committed,
+    /// return none if the block is kept as a side block.
+    ///
+    /// The longest chain wins, a chain of the same length never replaces the current head, and
+    /// a branch forking below the finalized height is never chosen.
+    pub fn fork_choice(&self, block: &Block) -> Result<Option<HeadUpdate>> {
+        let extend = HeadUpdate {
+            branch: vec![block.clone()],
+            old_head_index: None,
+        };
+        let head = match self.db.get_latest_block()? {
+            Some(head) => head,
+            None => return Ok(Some(extend)),
+        };
+
+        if block.header.previous_hash == head.hash()? {
+            Ok(Some(extend))
+        } else if block.header.index > head.header.index {
+            Ok(Some(self.reorg(&head, block)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// `reorg` rewind the state to the common ancestor of the head and the new head, then
+    /// replay the winning branch, which is linked as the canonical chain on commit.
+    fn reorg(&self, head: &Block, new_head: &Block) -> Result<HeadUpdate> {
+        let mut branch = vec![new_head.clone()];
+        let mut ancestor = self.db.get_block_by_hash(new_head.header.previous_hash)?;
+        while self.db.get_canonical_hash(ancestor.header.index)? != Some(ancestor.hash()?) {
+            if ancestor.header.index == 0 {
+                return Err("fork branch has a different genesis block"
+                    .to_string()
+                    .into());
+            }
+            branch.push(ancestor.clone());
+            ancestor = self.db.get_block_by_hash(ancestor.header.previous_hash)?;
+        }
+        if ancestor.header.index + 1 < self.finalized_height {
+            return Err(format!(
+                "fork from block {} reverts finalized blocks",
+                ancestor.header.index
+            )
+            .into());
+        }
+        branch.reverse();
+
+        // The replay only checks the roots, the branch states were written on import.
+        let (mut state, _) = self.parent_state(Some(&ancestor))?;
+        for block in &branch {
+            state.apply_block(block)?;
+            if state.roots() != StateRoots::from_header(&block.header) {
+                return Err(format!("fork block {} roots mismatch", block.header.index).into());
+            }
+        }
+
+        log::info!(
+            "reorg head {} to block {}, common ancestor {}",
+            head.header.index,
+            new_head.header.index,
+            ancestor.header.index
+        );
+        Ok(HeadUpdate {
+            branch,
+            old_head_index: Some(head.header.index),
+        })
+    }
+
+    /// `set_head` start the next block on the new head, and let the modules reopen their state
+    /// at the roots of the new head.
+    pub(crate) async fn set_head(&mut self, head: &Block) -> Result<()> {
+        self.start_next_block(head)?;
+        self.prune_tick();
+        for caller in [self.node_caller.as_mut(), self.task_caller.as_mut()]
+            .into_iter()
+            .flatten()
+        {
+            caller
+                .notify(Message::LocalMessage(LocalMessage::BlockNewHead(
+                    head.header.clone(),
+                )))
+                .await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,264 @@
+use std::{collections::HashSet, sync::Arc};
+
+use ethereum_types::H256;
+use kvdb::KeyValueDB;
+
+use crate::database::{self, AppDB};
+
+use super::{
+    db::{get_latest_hash, Block, BlockchainDB, Body, Header},
+    error::Result,
+    prune::state_tries,
+};
+
+/// `FsckReport` the result of `check_chain`, one message per problem found.
+#[derive(Debug, Default)]
+pub struct FsckReport {
+    pub checked_blocks: u64,
+    pub errors: Vec<String>,
+    pub repaired_indexes: usize,
+}
+
+impl FsckReport {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// `check_chain` walk the canonical chain from the head back to genesis, check that every block
+/// decodes, links to its parent and has resolvable state tries, then check the index keys of
+/// `COL_EXTRA` against the walked chain.
+///
+/// The state is only checked for the last `state_depth` blocks when set, older states may have
+/// been pruned. With `repair` the wrong index keys are rewritten from the walked chain.
+pub fn check_chain(
+    db: Arc<dyn KeyValueDB>,
+    state_depth: Option<u64>,
+    repair: bool,
+) -> Result<FsckReport> {
+    let mut report = FsckReport::default();
+    let blockchain_db = BlockchainDB::new(db.clone())?;
+    for (name, app_db) in [
+        ("header", &blockchain_db.header_db),
+        ("body", &blockchain_db.body_db),
+    ] {
+        if let Err(e) = app_db.collect_node_keys(&mut HashSet::new()) {
+            report
+                .errors
+                .push(format!("{} trie is not resolvable: {:?}", name, e));
+        }
+    }
+
+    let head_hash = match get_latest_hash(db.clone())? {
+        Some(hash) => hash,
+        None => return Ok(report),
+    };
+    let chain = walk_chain(&blockchain_db, head_hash, &mut report);
+    report.checked_blocks = chain.len() as u64;
+
+    if let Some((_, head)) = chain.first() {
+        let state_from = match state_depth {
+            Some(depth) => head.index.saturating_sub(depth.saturating_sub(1)),
+            None => 0,
+        };
+        let mut checked_roots = HashSet::new();
+        for (_, header) in chain.iter().filter(|(_, h)| h.index >= state_from) {
+            for (column, root) in state_tries(header) {
+                if !checked_roots.insert((column, root)) {
+                    continue;
+                }
+                let resolved = AppDB::new(db.clone(), column, root.to_fixed_bytes())
+                    .and_then(|app_db| app_db.collect_node_keys(&mut HashSet::new()));
+                if let Err(e) = resolved {
+                    report.errors.push(format!(
+                        "block {} state root {:?} of column {} is not resolvable: {:?}",
+                        header.index, root, column, e
+                    ));
+                }
+            }
+        }
+    }
+
+    check_indexes(&blockchain_db, &chain, repair, &mut report)?;
+    Ok(report)
+}
+
+/// `walk_chain` follow the parent hashes from the head, return the decoded headers from the head
+/// down to the last block reached.
+fn walk_chain(
+    blockchain_db: &BlockchainDB,
+    head_hash: H256,
+    report: &mut FsckReport,
+) -> Vec<(H256, Header)> {
+    let mut chain: Vec<(H256, Header)> = vec![];
+    let mut hash = head_hash;
+    loop {
+        let block = match read_block(blockchain_db, hash) {
+            Ok(block) => block,
+            Err(e) => {
+                report.errors.push(e);
+                break;
+            }
+        };
+        match block.hash() {
+            Ok(h) if h == hash => {}
+            _ => report
+                .errors
+                .push(format!("block {:?} does not match its hash", hash)),
+        }
+        if let Some((_, child)) = chain.last() {
+            if block.header.index + 1 != child.index {
+                report.errors.push(format!(
+                    "block {} links to parent {:?} at index {}",
+                    child.index, hash, block.header.index
+                ));
+                break;
+            }
+        }
+
+        let header = block.header;
+        let previous_hash = header.previous_hash;
+        let index = header.index;
+        chain.push((hash, header));
+        if index == 0 {
+            break;
+        }
+        hash = previous_hash;
+    }
+    chain
+}
+
+fn read_block(blockchain_db: &BlockchainDB, hash: H256) -> std::result::Result<Block, String> {
+    let header = match blockchain_db.header_db.get(hash.as_bytes()) {
+        Ok(Some(header)) => header,
+        Ok(None) => return Err(format!("header {:?} is missing", hash)),
+        Err(e) => return Err(format!("header {:?} is not readable: {:?}", hash, e)),
+    };
+    let header: Header = serde_cbor::from_slice(&header)
+        .map_err(|e| format!("header {:?} does not decode: {:?}", hash, e))?;
+    let body = match blockchain_db.body_db.get(hash.as_bytes()) {
+        Ok(Some(body)) => body,
+        Ok(None) => return Err(format!("body {:?} is missing", hash)),
+        Err(e) => return Err(format!("body {:?} is not readable: {:?}", hash, e)),
+    };
+    let body: Body = serde_cbor::from_slice(&body)
+        .map_err(|e| format!("body {:?} does not decode: {:?}", hash, e))?;
+    Ok(Block { header, body })
+}
+
+/// `check_indexes` check the index to hash keys of the walked chain, and that no index key is
+/// left above the head.
+fn check_indexes(
+    blockchain_db: &BlockchainDB,
+    chain: &[(H256, Header)],
+    repair: bool,
+    report: &mut FsckReport,
+) -> Result<()> {
+    let mut tx = blockchain_db.db.transaction();
+    for (hash, header) in chain {
+        let indexed = blockchain_db.get_canonical_hash(header.index)?;
+        if indexed == Some(*hash) {
+            continue;
+        }
+        report.errors.push(format!(
+            "index {} points to {:?} instead of {:?}",
+            header.index, indexed, hash
+        ));
+        tx.put(
+            database::db::COL_EXTRA,
+            &serde_cbor::to_vec(&header.index)?,
+            hash.as_bytes(),
+        );
+    }
+    if let Some((_, head)) = chain.first() {
+        let mut index = head.index + 1;
+        while let Some(hash) = blockchain_db.get_canonical_hash(index)? {
+            report.errors.push(format!(
+                "index {} above the head points to {:?}",
+                index, hash
+            ));
+            tx.delete(database::db::COL_EXTRA, &serde_cbor::to_vec(&index)?);
+            index += 1;
+        }
+    }
+
+    if repair && !tx.ops.is_empty() {
+        report.repaired_indexes = tx.ops.len();
+        blockchain_db.db.write(tx)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{blockchain::test_chain::TestChain, database::prune};
+
+    #[test]
+    fn test_check_chain() {
+        let db = database::open_memory_database();
+        let chain = TestChain::build(db.clone(), 3);
+        let blockchain_db = chain.blockchain_db;
+        let blocks = chain.blocks;
+
+        let report = check_chain(db.clone(), None, false).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.checked_blocks, 3);
+
+        // a wrong index key and a stale one above the head are repaired
+        let mut tx = db.transaction();
+        tx.put(
+            database::db::COL_EXTRA,
+            &serde_cbor::to_vec(&1u64).unwrap(),
+            H256::zero().as_bytes(),
+        );
+        tx.put(
+            database::db::COL_EXTRA,
+            &serde_cbor::to_vec(&3u64).unwrap(),
+            H256::zero().as_bytes(),
+        );
+        db.write(tx).unwrap();
+        let report = check_chain(db.clone(), None, true).unwrap();
+        assert_eq!(report.errors.len(), 2);
+        assert_eq!(report.repaired_indexes, 2);
+        assert!(check_chain(db.clone(), None, false).unwrap().is_ok());
+        assert_eq!(
+            blockchain_db.get_canonical_hash(1).unwrap(),
+            Some(blocks[1].hash().unwrap())
+        );
+
+        // missing trie nodes are reported
+        prune::sweep_column(&db, database::db::COL_TASK_LIST, &HashSet::new()).unwrap();
+        let report = check_chain(db, None, false).unwrap();
+        assert!(!report.is_ok());
+        assert!(report.errors[0].contains("is not resolvable"));
+    }
+
+    #[test]
+    fn test_check_corrupted_header() {
+        let db = database::open_memory_database();
+        let chain = TestChain::build(db.clone(), 3);
+        let mut blockchain_db = chain.blockchain_db;
+
+        // the parent of the head doesn't decode, the walk stops at the head
+        let parent_hash = chain.blocks[1].hash().unwrap();
+        blockchain_db
+            .header_db
+            .insert(parent_hash.as_bytes(), b"corrupted")
+            .unwrap();
+        let mut tx = db.transaction();
+        blockchain_db.header_db.drain_into(&mut tx).unwrap();
+        tx.put(
+            database::db::COL_BLOCK_HEADERS,
+            database::KEY_ROOT,
+            &blockchain_db.header_db.get_root(),
+        );
+        db.write(tx).unwrap();
+
+        let report = check_chain(db, None, true).unwrap();
+        assert_eq!(report.checked_blocks, 1);
+        assert!(report.errors[0].contains("does not decode"));
+        assert_eq!(report.repaired_indexes, 0);
+    }
+}
@@ -0,0 +1,255 @@
+use std::{fs::File, io::prelude::*, path::Path, sync::Arc};
+
+use ethereum_types::H256;
+use ic_agent::ic_types::Principal;
+use kvdb::KeyValueDB;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    database::{self, data_types::TaskType, overlay::OverlayDB, trie_layout},
+    ic::canister::node::Keeper,
+};
+
+use super::{
+    db::{get_latest_hash, Block, HeadUpdate, TaskOperation, TaskOperationType},
+    error::Result,
+    reward, set_body_roots,
+    state::{StateRoots, StateTransition},
+    BlockchainModule,
+};
+
+/// `ChainParams` the consensus parameters every node of the chain must share.
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize)]
+pub struct ChainParams {
+    pub block_time_millis: u64,
+    pub block_reward: u64,
+    /// State trie values at least this long are stored by hash outside the trie nodes.
+    #[serde(default = "default_inline_value_threshold")]
+    pub inline_value_threshold: u32,
+}
+
+fn default_inline_value_threshold() -> u32 {
+    trie_layout::INLINE_VALUE_THRESHOLD
+}
+
+impl Default for ChainParams {
+    fn default() -> Self {
+        ChainParams {
+            block_time_millis: 5000,
+            block_reward: reward::BLOCK_REWARD,
+            inline_value_threshold: trie_layout::INLINE_VALUE_THRESHOLD,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize)]
+pub struct GenesisTask {
+    pub id: u64,
+    pub binary_hash: H256,
+    pub task_type: TaskType,
+    pub node_limit: u64,
+    pub reward_weight: u64,
+}
+
+/// `GenesisBalance` the account is the principal text bound by the nodes.
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize)]
+pub struct GenesisBalance {
+    pub account: String,
+    pub amount: u64,
+}
+
+/// `GenesisSpec` describe the genesis block, loaded from a toml or json file.
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize)]
+pub struct GenesisSpec {
+    pub chain_id: String,
+    #[serde(default)]
+    pub keepers: Vec<String>,
+    #[serde(default)]
+    pub tasks: Vec<GenesisTask>,
+    #[serde(default)]
+    pub balances: Vec<GenesisBalance>,
+    #[serde(default)]
+    pub params: ChainParams,
+}
+
+impl GenesisSpec {
+    /// `load` read the spec from the file, files ending with `.json` are json, others are toml.
+    pub fn load(file_path: &str) -> Result<Self> {
+        let mut file = File::open(file_path)?;
+        let mut str_val = String::new();
+        file.read_to_string(&mut str_val)?;
+        let is_json = Path::new(file_path)
+            .extension()
+            .map_or(false, |ext| ext == "json");
+        let spec: GenesisSpec = if is_json {
+            serde_json::from_str(&str_val)?
+        } else {
+            toml::from_str(&str_val)?
+        };
+        trie_layout::check_inline_threshold(spec.params.inline_value_threshold)?;
+        Ok(spec)
+    }
+
+    /// `initial_keepers` the keepers allowed to pack blocks before the keeper set is loaded
+    /// from the node canister.
+    pub fn initial_keepers(&self) -> Result<Keeper> {
+        let mut keepers = Keeper::new();
+        for keeper in &self.keepers {
+            match Principal::from_text(keeper) {
+                Ok(principal) => keepers.insert(principal),
+                Err(e) => return Err(format!("invalid genesis keeper {}: {:?}", keeper, e).into()),
+            };
+        }
+        Ok(keepers)
+    }
+
+    /// `task_operations` the add operations of the genesis tasks.
+    pub fn task_operations(&self) -> Vec<TaskOperation> {
+        self.tasks
+            .iter()
+            .map(|task| TaskOperation {
+                id: task.id,
+                operation: TaskOperationType::Add,
+                binary_hash: task.binary_hash,
+                task_type: task.task_type.clone(),
+                node_limit: task.node_limit,
+                reward_weight: task.reward_weight,
+            })
+            .collect()
+    }
+
+    /// `balance_accounts` the genesis balances by account bytes, the CBOR of the principal text
+    /// like the reward accounts. The account trie keys them by `account::account_key`.
+    pub fn balance_accounts(&self) -> Result<Vec<(Vec<u8>, u64)>> {
+        let mut balances = vec![];
+        for balance in &self.balances {
+            balances.push((serde_cbor::to_vec(&balance.account)?, balance.amount));
+        }
+        Ok(balances)
+    }
+}
+
+/// `build_genesis_state` apply the genesis tasks and balances on the empty state.
+pub fn build_genesis_state(
+    db: Arc<dyn KeyValueDB>,
+    spec: &GenesisSpec,
+    block: &mut Block,
+) -> Result<()> {
+    let mut state = StateTransition::new(db, &StateRoots::default(), &spec.params)?;
+    block.body.tasks = spec.task_operations();
+    state.apply_body(0, &block.body)?;
+    for (account, amount) in spec.balance_accounts()? {
+        state.add_balance(&account, amount)?;
+    }
+    state.roots().write_to_header(&mut block.header);
+    state.commit()
+}
+
+/// `build_genesis_block` build the genesis block of the spec, its state is buffered in the
+/// returned overlay.
+pub fn build_genesis_block(
+    db: Arc<dyn KeyValueDB>,
+    spec: &GenesisSpec,
+) -> Result<(Block, Arc<OverlayDB>)> {
+    let mut genesis = Block::default();
+    genesis.header.chain_id = spec.chain_id.clone();
+    let state = OverlayDB::new(db.clone());
+    build_genesis_state(state.clone(), spec, &mut genesis)?;
+    set_body_roots(db, &mut genesis, &spec.params)?;
+    Ok((genesis, state))
+}
+
+/// `genesis_hash` the hash of the genesis block of the spec.
+pub fn genesis_hash(spec: &GenesisSpec) -> Result<H256> {
+    let (genesis, _) = build_genesis_block(database::open_memory_database(), spec)?;
+    genesis.hash()
+}
+
+impl BlockchainModule {
+    /// `init_genesis` build the genesis block from the spec, store it on a new chain, or check
+    /// it's the genesis block of the stored chain.
+    pub fn init_genesis(&mut self) -> Result<()> {
+        let (genesis, state) = build_genesis_block(self.db.db.clone(), &self.genesis)?;
+        let genesis_hash = genesis.hash()?;
+
+        match self.db.get_genesis_hash()? {
+            Some(hash) if hash == genesis_hash => {}
+            Some(hash) => {
+                return Err(format!(
+                    "genesis hash mismatch, stored {:?}, spec {:?}",
+                    hash, genesis_hash
+                )
+                .into());
+            }
+            // a chain without genesis hash must not be reset to the genesis block
+            None if get_latest_hash(self.db.db.clone())?.is_some() => {
+                return Err("stored chain has no genesis hash".to_string().into());
+            }
+            None => {
+                let head_update = HeadUpdate {
+                    branch: vec![genesis.clone()],
+                    old_head_index: None,
+                };
+                self.db
+                    .commit_block(&genesis, Some(&state), Some(&head_update))?;
+                log::info!("init genesis block {:?}", genesis_hash);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{blockchain::account::AccountDB, database};
+
+    const GENESIS_TOML: &str = r#"
+chain_id = "wdn-test"
+keepers = []
+
+[params]
+block_time_millis = 1000
+block_reward = 500
+
+[[tasks]]
+id = 1
+binary_hash = "0x0000000000000000000000000000000000000000000000000000000000000001"
+task_type = "LongTerm"
+node_limit = 100
+reward_weight = 100
+
+[[balances]]
+account = "aaaaa-aa"
+amount = 1000
+
+[[balances]]
+account = "hpikg-6exdt-jn33w-ndty3-fc7jc-tl2lr-buih3-cs3y7-tftkp-sfp62-gqe"
+amount = 2000
+"#;
+
+    #[test]
+    fn test_genesis_deterministic() {
+        let spec: GenesisSpec = toml::from_str(GENESIS_TOML).unwrap();
+        assert_eq!(spec.params.block_reward, 500);
+        assert_eq!(spec.tasks[0].binary_hash, H256::from_low_u64_be(1));
+
+        let mut genesis_hashes = vec![];
+        for _ in 0..2 {
+            let db = database::open_memory_database();
+            let mut genesis = Block::default();
+            build_genesis_state(db.clone(), &spec, &mut genesis).unwrap();
+            assert!(!genesis.header.account_root.is_zero());
+            let account_db =
+                AccountDB::new(db, genesis.header.account_root, genesis.header.reward_root)
+                    .unwrap();
+            for balance in &spec.balances {
+                let account = serde_cbor::to_vec(&balance.account).unwrap();
+                assert_eq!(account_db.get_balance(&account).unwrap(), balance.amount);
+            }
+            genesis_hashes.push(genesis.hash().unwrap());
+        }
+        assert_eq!(genesis_hashes[0], genesis_hashes[1]);
+    }
+}
@@ -0,0 +1,196 @@
+use std::{result, sync::Arc};
+
+use kvdb::KeyValueDB;
+
+use crate::{
+    database::data_types::{NodeData, TaskData},
+    message::ReqError,
+    node::db::NodeDB,
+    task::db::TaskDB,
+};
+
+use super::{
+    account::AccountDB,
+    db::{BlockchainDB, Header},
+    error::Result,
+    prune, BlockchainModule,
+};
+
+/// `StateView` read only views of the state tries at the roots of a block header.
+///
+/// An archive node keeps the state of every stored block, a pruned node only the states from
+/// `prune::kept_from` on. Writes on the views are never committed.
+pub struct StateView {
+    pub header: Header,
+    pub task_db: TaskDB,
+    pub node_db: NodeDB,
+    pub account_db: AccountDB,
+}
+
+impl StateView {
+    pub fn open(backend: Arc<dyn KeyValueDB>, header: Header) -> Result<Self> {
+        let task_db = TaskDB::with_roots(
+            backend.clone(),
+            header.task_root,
+            header.task_operation_root,
+            header.task_result_root,
+        )?;
+        let node_db = NodeDB::with_roots(
+            backend.clone(),
+            header.node_root,
+            header.node_activation_root,
+        )?;
+        let account_db = AccountDB::new(backend, header.account_root, header.reward_root)?;
+        Ok(StateView {
+            header,
+            task_db,
+            node_db,
+            account_db,
+        })
+    }
+
+    pub fn list_tasks(&self, start_id: Option<u64>, limit: usize) -> Result<Vec<TaskData>> {
+        Ok(self.task_db.list_tasks(start_id, limit)?)
+    }
+
+    pub fn list_nodes(&self, start_peer_id: Option<&str>, limit: usize) -> Result<Vec<NodeData>> {
+        Ok(self.node_db.list_nodes(start_peer_id, limit)?)
+    }
+
+    pub fn get_balance(&self, account: &[u8]) -> Result<u64> {
+        self.account_db.get_balance(account)
+    }
+}
+
+/// `state_at` open the state of the canonical block at the index. A block above the head is not
+/// found, the state of a block below `kept_from` is gone.
+pub fn state_at(
+    blockchain_db: &BlockchainDB,
+    index: u64,
+    kept_from: u64,
+) -> result::Result<StateView, ReqError> {
+    let head = match blockchain_db.get_latest_block() {
+        Ok(Some(head)) => head,
+        Ok(None) => return Err(ReqError::NotFound("blockchain has no block".to_owned())),
+        Err(e) => return Err(ReqError::Internal(e.message)),
+    };
+    if index > head.header.index {
+        return Err(ReqError::NotFound(format!(
+            "block {} is above the head {}",
+            index, head.header.index
+        )));
+    }
+    if index < kept_from {
+        return Err(ReqError::Gone(format!(
+            "state of block {} is pruned",
+            index
+        )));
+    }
+    let block = blockchain_db
+        .get_block_by_index(index)
+        .map_err(|e| ReqError::NotFound(e.message))?;
+    StateView::open(blockchain_db.db.clone(), block.header)
+        .map_err(|e| ReqError::Internal(e.message))
+}
+
+impl BlockchainModule {
+    /// `state_at` open the state of the canonical block at the index, within the states kept by
+    /// a pruned node.
+    pub fn state_at(&self, index: u64) -> result::Result<StateView, ReqError> {
+        let kept_from = match self.prune_depth {
+            Some(history_depth) => prune::kept_from(
+                self.current_block.header.index.saturating_sub(1),
+                history_depth,
+                self.finalized_height,
+            ),
+            None => 0,
+        };
+        state_at(&self.db, index, kept_from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ethereum_types::H256;
+
+    use crate::{
+        blockchain::{
+            db::{Body, TaskOperation, TaskOperationType},
+            genesis::ChainParams,
+            state::{StateRoots, StateTransition},
+            test_chain::TestChain,
+        },
+        database::{
+            self,
+            data_types::{TaskStatus, TaskType},
+        },
+    };
+
+    fn task_operation(operation: TaskOperationType) -> Body {
+        let mut body = Body::new();
+        body.tasks.push(TaskOperation {
+            id: 1,
+            operation,
+            binary_hash: H256::repeat_byte(1),
+            task_type: TaskType::LongTerm,
+            node_limit: 100,
+            reward_weight: 100,
+        });
+        body
+    }
+
+    #[test]
+    fn test_state_at_old_roots() {
+        let db = database::open_memory_database();
+
+        let mut headers = vec![];
+        let mut roots = StateRoots::default();
+        for operation in [TaskOperationType::Add, TaskOperationType::Disable] {
+            let mut state =
+                StateTransition::new(db.clone(), &roots, &ChainParams::default()).unwrap();
+            state.apply_body(0, &task_operation(operation)).unwrap();
+            state.commit().unwrap();
+            roots = state.roots();
+            let mut header = Header::default();
+            roots.write_to_header(&mut header);
+            headers.push(header);
+        }
+
+        let old_state = StateView::open(db.clone(), headers[0].clone()).unwrap();
+        assert_eq!(
+            old_state.list_tasks(None, 10).unwrap()[0].status,
+            TaskStatus::Enable
+        );
+        let new_state = StateView::open(db, headers[1].clone()).unwrap();
+        assert_eq!(
+            new_state.list_tasks(None, 10).unwrap()[0].status,
+            TaskStatus::Disable
+        );
+    }
+
+    #[test]
+    fn test_state_at_missing_or_pruned() {
+        let db = database::open_memory_database();
+        let mut chain = TestChain::build(db.clone(), 4);
+        chain.blockchain_db.set_finalized_height(4).unwrap();
+        let blockchain_db = chain.blockchain_db;
+
+        let state = state_at(&blockchain_db, 3, 0).unwrap();
+        assert_eq!(state.list_tasks(None, 10).unwrap().len(), 4);
+        assert!(matches!(
+            state_at(&blockchain_db, 4, 0),
+            Err(ReqError::NotFound(_))
+        ));
+
+        let kept_from = prune::kept_from(3, 2, 4);
+        assert_eq!(kept_from, 2);
+        prune::prune_state(db, 2).unwrap();
+        assert!(state_at(&blockchain_db, 2, kept_from).is_ok());
+        assert!(matches!(
+            state_at(&blockchain_db, 1, kept_from),
+            Err(ReqError::Gone(_))
+        ));
+    }
+}
@@ -1,23 +1,54 @@
 use std::{sync::Arc, thread, time::Duration};
 
+use chrono::Local;
 use ethereum_types::H256;
+use hash_db::Hasher;
+use ic_agent::{ic_types::Principal, Identity};
+use keccak_hasher::KeccakHasher;
 use kvdb::KeyValueDB;
+use libp2p::{identity::Keypair, PeerId};
+use parity_scale_codec::Encode;
 
 use crate::{
-    ic::wdn_identity::WdnIdentity,
-    message::{Caller, LocalMessage, LocalMessageModule, Message, Waiter},
+    database::{self, overlay::OverlayDB},
+    ic::{self, canister::node::Keeper, wdn_identity::WdnIdentity},
+    message::{Caller, LocalMessage, LocalMessageModule, Message, ReqError, Waiter},
     network::{
-        topics::{TopicMessage, Topics},
-        NetworkModule,
+        topics::{SubTopics, TopicMessage, Topics},
+        NetworkMessage, NetworkModule,
     },
 };
 use async_std::task;
 
-use self::db::{Block, BlockchainDB, NeedSignData, NodeActivation, TaskOperation};
+use self::config::BlockchainConfig;
+use self::db::{
+    Block, BlockchainDB, Body, HeadUpdate, Header, NeedSignData, NodeActivation, Reward,
+    TaskOperation, TaskResult,
+};
 use self::error::Result;
+use self::finality::VoteCollector;
+use self::genesis::{ChainParams, GenesisSpec};
+use self::state::{StateRoots, StateTransition};
 
+pub mod account;
+pub mod config;
 pub mod db;
 pub mod error;
+pub mod finality;
+pub mod fork;
+pub mod fsck;
+pub mod genesis;
+pub mod history;
+pub mod proof;
+pub mod prune;
+pub mod reward;
+pub mod schedule;
+pub mod signature;
+pub mod snapshot;
+pub mod state;
+pub mod sync;
+#[cfg(test)]
+pub(crate) mod test_chain;
 
 pub struct BlockchainModule {
     db: BlockchainDB,
@@ -25,21 +56,258 @@ pub struct BlockchainModule {
     network_caller: Caller,
     message_subscribe: Vec<(Topics, Caller)>,
     current_block: Block,
+    local_key: libp2p::identity::ed25519::Keypair,
+    wdn_identity: WdnIdentity,
+    agent: ic_agent::Agent,
+    keepers: Keeper,
+    sync_target: sync::SyncTarget,
+    genesis: GenesisSpec,
+    finalized_height: u64,
+    votes: VoteCollector,
+    pub node_caller: Option<Caller>,
+    pub task_caller: Option<Caller>,
+    pub prune_depth: Option<u64>,
 }
 
 impl BlockchainModule {
-    pub fn new(db_backend: Arc<dyn KeyValueDB>) -> Result<BlockchainModule> {
+    pub fn new(
+        db_backend: Arc<dyn KeyValueDB>,
+        local_key: Keypair,
+        config: BlockchainConfig,
+    ) -> Result<BlockchainModule> {
+        let genesis = GenesisSpec::load(&config.genesis_path)?;
+        BlockchainModule::with_genesis(db_backend, local_key, genesis)
+    }
+
+    /// `with_genesis` open the chain of the genesis spec, and resume it from the stored head.
+    pub fn with_genesis(
+        db_backend: Arc<dyn KeyValueDB>,
+        local_key: Keypair,
+        genesis: GenesisSpec,
+    ) -> Result<BlockchainModule> {
         let db = BlockchainDB::new(db_backend)?;
+        let finalized_height = db.get_finalized_height()?;
+        let keepers = genesis.initial_keepers()?;
         let message_waiter = Waiter::new();
-        let message_subscribe = vec![];
+        let message_subscribe = vec![
+            (Topics::NewBlock, message_waiter.get_caller()),
+            (Topics::DataSync, message_waiter.get_caller()),
+            (Topics::TaskResult, message_waiter.get_caller()),
+            (Topics::Vote, message_waiter.get_caller()),
+        ];
         let block = Block::default();
-        Ok(BlockchainModule {
+
+        let local_key = match local_key {
+            Keypair::Ed25519(ed25519key) => ed25519key,
+            _ => return Err("blockchain module can not get local key".to_string().into()),
+        };
+        let wdn_identity = WdnIdentity::from_key_pair(local_key.clone());
+        let agent = ic::create_agent_with_identity(wdn_identity.clone(), ic::IC_URL)?;
+
+        let mut blockchain_module = BlockchainModule {
             db: db,
             network_caller: message_waiter.get_caller(),
             message_waiter: Some(message_waiter),
             message_subscribe: message_subscribe,
             current_block: block,
-        })
+            local_key,
+            wdn_identity,
+            agent,
+            keepers,
+            sync_target: sync::SyncTarget::default(),
+            genesis,
+            finalized_height,
+            votes: VoteCollector::default(),
+            node_caller: None,
+            task_caller: None,
+            prune_depth: None,
+        };
+
+        // Resume the chain from the stored head, a new chain starts from the genesis block.
+        blockchain_module.init_genesis()?;
+        if let Some(latest_block) = blockchain_module.db.get_latest_block()? {
+            log::info!("resume blockchain from block {}", latest_block.header.index);
+            blockchain_module.start_next_block(&latest_block)?;
+        }
+        Ok(blockchain_module)
+    }
+
+    /// `refresh_keepers` reload the keeper set from the node canister.
+    fn refresh_keepers(&mut self) -> Result<()> {
+        self.keepers = ic::get_keepers(self.agent.clone(), self.local_key.clone())?;
+        Ok(())
+    }
+
+    /// `verify_block` check the block header was signed by a keeper of the current keeper set,
+    /// and the keeper is the scheduled producer of the slot of the block timestamp.
+    pub fn verify_block(&self, parent: &Block, block: &Block) -> Result<()> {
+        signature::verify_header(&block.header, &self.keepers)?;
+
+        let now = Local::now().timestamp_millis() as u64;
+        if block.header.timestamp > now + self.genesis.params.block_time_millis {
+            return Err("block timestamp is in the future".to_string().into());
+        }
+        let producer = self.slot_producer(parent, block.header.timestamp);
+        let minter = ic::wdn_identity::principal_from_der(&block.header.minter);
+        if producer != Some(minter) {
+            return Err(format!(
+                "block {} is not packed by the scheduled producer",
+                block.header.index
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// `slot_producer` the keeper scheduled to pack the child block of the parent at the timestamp.
+    fn slot_producer(&self, parent: &Block, timestamp: u64) -> Option<Principal> {
+        let slot_offset = schedule::slot_offset(
+            parent.header.timestamp,
+            timestamp,
+            self.genesis.params.block_time_millis,
+        )?;
+        schedule::slot_producer(
+            &schedule::sorted_keepers(&self.keepers),
+            parent.header.index + 1,
+            slot_offset,
+        )
+    }
+
+    /// `set_body_roots` fill the `current_*` roots of the header from the block body.
+    fn set_body_roots(&self, block: &mut Block) -> Result<()> {
+        set_body_roots(self.db.db.clone(), block, &self.genesis.params)
+    }
+
+    /// `parent_state` open the state at the roots of the parent block, the state writes are
+    /// buffered in the returned overlay until the block is committed.
+    fn parent_state(&self, parent: Option<&Block>) -> Result<(StateTransition, Arc<OverlayDB>)> {
+        let parent_roots = match parent {
+            Some(parent) => StateRoots::from_header(&parent.header),
+            None => StateRoots::default(),
+        };
+        let overlay = OverlayDB::new(self.db.db.clone());
+        let state = StateTransition::new(overlay.clone(), &parent_roots, &self.genesis.params)?;
+        Ok((state, overlay))
+    }
+
+    /// `execute_block` apply the block on the state of the parent block,
+    /// then write the result state roots into the block header.
+    fn execute_block(&self, parent: Option<&Block>, block: &mut Block) -> Result<Arc<OverlayDB>> {
+        let (mut state, overlay) = self.parent_state(parent)?;
+        state.apply_block(block)?;
+        state.roots().write_to_header(&mut block.header);
+        state.commit()?;
+        Ok(overlay)
+    }
+
+    /// `commit_block` store the block with its state in one transaction, and move the head if
+    /// the fork choice picks the block.
+    async fn commit_block(&mut self, block: &Block, state: &OverlayDB) -> Result<()> {
+        let head_update = self.fork_choice(block)?;
+        self.db
+            .commit_block(block, Some(state), head_update.as_ref())?;
+        match head_update {
+            Some(_) => self.set_head(block).await,
+            None => {
+                log::info!("keep side block {}", block.header.index);
+                Ok(())
+            }
+        }
+    }
+
+    /// `save_task_result` collect the task result uploaded by the worker into the current block.
+    fn save_task_result(&mut self, mut task_result: TaskResult, peer_id: PeerId) {
+        task_result.peer_id = peer_id.to_base58();
+        self.current_block.body.task_results.push(task_result);
+    }
+
+    /// `get_block_rewards` get the rewards distributed in the block, the block must be final.
+    pub fn get_block_rewards(&self, index: u64) -> Result<Vec<Reward>> {
+        self.check_final(index)?;
+        Ok(self.db.get_block_by_index(index)?.body.reward)
+    }
+
+    /// `start_next_block` reset the current block as the child of the parent block,
+    /// the state roots are inherited from the parent.
+    fn start_next_block(&mut self, parent: &Block) -> Result<()> {
+        let header = Header {
+            chain_id: parent.header.chain_id.clone(),
+            index: parent.header.index + 1,
+            previous_hash: parent.hash()?,
+            account_root: parent.header.account_root,
+            reward_root: parent.header.reward_root,
+            task_root: parent.header.task_root,
+            task_operation_root: parent.header.task_operation_root,
+            task_result_root: parent.header.task_result_root,
+            node_root: parent.header.node_root,
+            node_activation_root: parent.header.node_activation_root,
+            version: parent.header.version,
+            ..Default::default()
+        };
+        self.current_block = Block {
+            header,
+            body: Body::new(),
+        };
+        Ok(())
+    }
+
+    /// `import_block` validate a block received from peer on the state of its parent,
+    /// then store it and apply the fork choice.
+    pub async fn import_block(&mut self, block: Block) -> Result<()> {
+        if self.db.has_block(block.hash()?)? {
+            return Ok(());
+        }
+        // Every node builds the genesis block from the genesis spec, it's never imported.
+        if block.header.index == 0 {
+            return Err("genesis block mismatch".to_string().into());
+        }
+        if !self.db.has_block(block.header.previous_hash)? {
+            return Err(format!("parent of block {} is unknown", block.header.index).into());
+        }
+        let parent = self.db.get_block_by_hash(block.header.previous_hash)?;
+        if block.header.index != parent.header.index + 1 {
+            return Err(format!(
+                "block index {} is not continuous with {}",
+                block.header.index, parent.header.index
+            )
+            .into());
+        }
+        if block.header.chain_id != parent.header.chain_id {
+            return Err(format!("block chain id {} mismatch", block.header.chain_id).into());
+        }
+
+        if self.keepers.is_empty() {
+            self.refresh_keepers()?;
+        }
+        self.verify_block(&parent, &block)?;
+
+        // Replay the body on the parent state, every root of the header must match.
+        let mut expected_block = block.clone();
+        self.set_body_roots(&mut expected_block)?;
+        let state = self.execute_block(Some(&parent), &mut expected_block)?;
+        if expected_block.header != block.header {
+            return Err("block roots mismatch".to_string().into());
+        }
+
+        self.commit_block(&block, &state).await?;
+        log::info!("import block {}", block.header.index);
+        Ok(())
+    }
+
+    /// `broadcast_block` publish the block to the peers on `Topics::NewBlock`.
+    async fn broadcast_block(&mut self, block: &Block) -> Result<()> {
+        let topic_msg = TopicMessage {
+            sub_topic: SubTopics::NewBlock,
+            data: serde_cbor::to_vec(block)?,
+        };
+        self.network_caller
+            .notify(Message::NetworkMessage(NetworkMessage {
+                peer_id: None,
+                topic: Topics::NewBlock,
+                message: serde_cbor::to_vec(&topic_msg)?,
+            }))
+            .await?;
+        Ok(())
     }
 
     fn save_node_activation(
@@ -76,37 +344,46 @@ impl BlockchainModule {
         Ok(())
     }
 
+    /// `start_tick` load the keeper set, then the block tick packs blocks in the slots of this keeper.
     fn start_tick(&mut self) -> Result<()> {
         log::info!("start blockchain tick!");
-        let caller = self.network_caller.clone();
-        thread::spawn(|| async move {
-            loop {
-                thread::sleep(Duration::from_millis(1000));
-                let res = caller
-                    .clone()
-                    .call(Message::LocalMessage(LocalMessage::ReqBlockPack()))
-                    .await;
-                if res.is_ok() {
-                    log::info!("blockchain send pack message success!")
-                } else {
-                    log::info!("blockchain send pack message fail!")
-                }
-            }
-        });
-        Ok(())
+        self.refresh_keepers()
     }
 
-    /// `pack_block` will pack a block append to the blockchain
-    pub fn pack_block(&mut self) -> Result<()> {
+    /// `is_my_slot` check this node is the scheduled producer of the next block at the timestamp.
+    /// It doesn't produce while syncing to a keeper signed head, the target is dropped when the
+    /// sync stalls.
+    fn is_my_slot(&self, now: u64) -> Result<bool> {
+        let principal = self.wdn_identity.sender()?;
+        if !self.keepers.contains(&principal) {
+            return Ok(false);
+        }
+        if self.sync_status().state != sync::SyncState::CaughtUp {
+            return Ok(false);
+        }
+        let parent = match self.db.get_latest_block()? {
+            Some(parent) => parent,
+            None => return Ok(false),
+        };
+        Ok(self.slot_producer(&parent, now) == Some(principal))
+    }
+
+    /// `pack_block` will pack a block append to the blockchain, then broadcast it to the peers.
+    pub async fn pack_block(&mut self, timestamp: u64) -> Result<()> {
         log::info!("Pack Block!");
-        let need_pack_block = self.current_block.clone();
-        // Distribute reward here
-        let total_task_weight: u64 = need_pack_block.body.task_results.iter().map(|x| x.id).sum();
-        let res = self.db.insert_block(need_pack_block);
-        let last_index = self.current_block.header.index.clone();
-        self.current_block = Block::default();
-        self.current_block.header.index = last_index + 1;
-        Ok(())
+        let mut need_pack_block = self.current_block.clone();
+        let parent = self.db.get_latest_block()?;
+        need_pack_block.body.reward = self
+            .parent_state(parent.as_ref())?
+            .0
+            .calc_rewards(&need_pack_block.body.task_results)?;
+        need_pack_block.header.timestamp = timestamp;
+        self.set_body_roots(&mut need_pack_block)?;
+        let state = self.execute_block(parent.as_ref(), &mut need_pack_block)?;
+        signature::sign_header(&mut need_pack_block.header, &self.wdn_identity)?;
+        self.commit_block(&need_pack_block, &state).await?;
+        self.broadcast_block(&need_pack_block).await?;
+        self.vote_block(&need_pack_block).await
     }
 }
 
@@ -127,7 +404,30 @@ impl LocalMessageModule for BlockchainModule {
 }
 
 pub fn run(blockchain_module: BlockchainModule) {
+    let mut caller = blockchain_module.get_message_caller();
+    let mut block_tick_caller = blockchain_module.get_message_caller();
     thread::spawn(move || task::block_on(watch_msg(blockchain_module)));
+
+    // block tick
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_millis(schedule::BLOCK_TICK_INTERVAL_MILLIS));
+        let res = task::block_on(
+            block_tick_caller.notify(Message::LocalMessage(LocalMessage::BlockTick())),
+        );
+        if res.is_err() {
+            log::error!("blockchain send block tick fail: {:?}", res);
+        }
+    });
+
+    // sync tick
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_millis(sync::SYNC_TICK_INTERVAL_MILLIS));
+        let res =
+            task::block_on(caller.notify(Message::LocalMessage(LocalMessage::BlockSyncTick())));
+        if res.is_err() {
+            log::error!("blockchain send sync tick fail: {:?}", res);
+        }
+    });
 }
 
 async fn watch_msg(mut blockchain_module: BlockchainModule) {
@@ -143,8 +443,19 @@ async fn watch_msg(mut blockchain_module: BlockchainModule) {
         .wait(|msg| match msg {
             crate::message::Message::NetworkMessage(network_msg) => {
                 log::info!("Receive peer msg!");
-                let topic_msg: TopicMessage = serde_cbor::from_slice(&network_msg.message).unwrap();
-                task::block_on(deal_peer_message(&mut blockchain_module, &topic_msg));
+                // peer messages are untrusted, a malformed one is dropped
+                let topic_msg: TopicMessage = match serde_cbor::from_slice(&network_msg.message) {
+                    Ok(topic_msg) => topic_msg,
+                    Err(e) => {
+                        log::error!("parse peer message fail: {:?}", e);
+                        return None;
+                    }
+                };
+                task::block_on(deal_peer_message(
+                    &mut blockchain_module,
+                    network_msg.peer_id,
+                    &topic_msg,
+                ));
                 None
             }
             crate::message::Message::LocalMessage(local_msg) => {
@@ -154,8 +465,60 @@ async fn watch_msg(mut blockchain_module: BlockchainModule) {
         .await;
 }
 
-async fn deal_peer_message(blockchain_module: &mut BlockchainModule, msg: &TopicMessage) {
-    match msg {
+async fn deal_peer_message(
+    blockchain_module: &mut BlockchainModule,
+    source: Option<PeerId>,
+    msg: &TopicMessage,
+) {
+    match msg.sub_topic {
+        SubTopics::UploadTaskData => {
+            let task_result: TaskResult = match serde_cbor::from_slice(&msg.data) {
+                Ok(task_result) => task_result,
+                Err(e) => {
+                    log::error!("parse task result fail: {:?}", e);
+                    return;
+                }
+            };
+            match source {
+                Some(peer_id) => blockchain_module.save_task_result(task_result, peer_id),
+                None => log::error!("task result without source peer"),
+            }
+        }
+        SubTopics::NewBlock => {
+            let block: Block = match serde_cbor::from_slice(&msg.data) {
+                Ok(block) => block,
+                Err(e) => {
+                    log::error!("parse new block fail: {:?}", e);
+                    return;
+                }
+            };
+            if let Err(e) = blockchain_module.import_block(block.clone()).await {
+                log::error!("import block fail: {:?}", e);
+                // a keeper block above the head means this node is behind, sync up to it
+                if block.header.index >= blockchain_module.current_block.header.index {
+                    if let Err(e) = blockchain_module.update_sync_target(&block.header, source) {
+                        log::error!("update sync target fail: {:?}", e);
+                    }
+                }
+                return;
+            }
+            if let Err(e) = blockchain_module.vote_block(&block).await {
+                log::error!("vote block fail: {:?}", e);
+            }
+        }
+        SubTopics::Vote => {
+            if let Err(e) = blockchain_module.deal_vote_message(msg).await {
+                log::error!("deal vote message fail: {:?}", e);
+            }
+        }
+        SubTopics::ReqSyncHead(_)
+        | SubTopics::AckSyncHead(_)
+        | SubTopics::ReqSyncBlocks(_, _, _, _)
+        | SubTopics::AckSyncBlocks => {
+            if let Err(e) = blockchain_module.deal_sync_message(source, msg).await {
+                log::error!("deal sync message fail: {:?}", e);
+            }
+        }
         _ => {}
     }
 }
@@ -168,6 +531,91 @@ async fn deal_local_message(
         LocalMessage::ReqBlockCurrent() => Some(Message::LocalMessage(
             LocalMessage::AckBlockCurrent(blockchain_module.current_block.clone()),
         )),
+        LocalMessage::BlockSyncTick() => {
+            if let Err(e) = blockchain_module.sync_tick().await {
+                log::error!("blockchain sync tick fail: {:?}", e);
+            }
+            None
+        }
+        LocalMessage::ReqBlockSyncStatus() => Some(Message::LocalMessage(
+            LocalMessage::AckBlockSyncStatus(blockchain_module.sync_status()),
+        )),
+        LocalMessage::ReqBlockFinality() => Some(Message::LocalMessage(
+            LocalMessage::AckBlockFinality(blockchain_module.finality_status()),
+        )),
+        LocalMessage::ReqStateProof(trie, keys) => {
+            match blockchain_module.get_state_proof(*trie, keys) {
+                Ok(state_proof) => Some(Message::LocalMessage(LocalMessage::AckStateProof(
+                    state_proof,
+                ))),
+                Err(e) => {
+                    log::error!("get state proof fail: {:?}", e);
+                    // a missing key is proven absent, only an unreadable trie fails
+                    Some(Message::LocalMessage(LocalMessage::AckError(
+                        ReqError::Internal(e.message),
+                    )))
+                }
+            }
+        }
+        LocalMessage::ReqHistoryTasks(index, start_id, limit) => {
+            let tasks = blockchain_module.state_at(*index).and_then(|state| {
+                state
+                    .list_tasks(*start_id, *limit)
+                    .map_err(|e| ReqError::Internal(e.message))
+            });
+            match tasks {
+                Ok(tasks) => Some(Message::LocalMessage(LocalMessage::AckTaskPage(tasks))),
+                Err(e) => {
+                    log::error!("list tasks at block {} fail: {:?}", index, e);
+                    Some(Message::LocalMessage(LocalMessage::AckError(e)))
+                }
+            }
+        }
+        LocalMessage::ReqHistoryNodes(index, start_peer_id, limit) => {
+            let nodes = blockchain_module.state_at(*index).and_then(|state| {
+                state
+                    .list_nodes(start_peer_id.as_deref(), *limit)
+                    .map_err(|e| ReqError::Internal(e.message))
+            });
+            match nodes {
+                Ok(nodes) => Some(Message::LocalMessage(LocalMessage::AckNodePage(nodes))),
+                Err(e) => {
+                    log::error!("list nodes at block {} fail: {:?}", index, e);
+                    Some(Message::LocalMessage(LocalMessage::AckError(e)))
+                }
+            }
+        }
+        LocalMessage::ReqHistoryBalance(index, account) => {
+            let balance = blockchain_module.state_at(*index).and_then(|state| {
+                state
+                    .get_balance(account)
+                    .map_err(|e| ReqError::Internal(e.message))
+            });
+            match balance {
+                Ok(balance) => Some(Message::LocalMessage(LocalMessage::AckHistoryBalance(
+                    balance,
+                ))),
+                Err(e) => {
+                    log::error!("get balance at block {} fail: {:?}", index, e);
+                    Some(Message::LocalMessage(LocalMessage::AckError(e)))
+                }
+            }
+        }
+        LocalMessage::ReqBlockRewards(index) => match blockchain_module.get_block_rewards(*index) {
+            Ok(rewards) => Some(Message::LocalMessage(LocalMessage::AckBlockRewards(
+                rewards,
+            ))),
+            Err(e) => {
+                log::error!("get block rewards fail: {:?}", e);
+                // the rewards of a block are only answered once it's final
+                let err = if *index < blockchain_module.finalized_height {
+                    ReqError::Internal(e.message)
+                } else {
+                    ReqError::NotFound(e.message)
+                };
+                Some(Message::LocalMessage(LocalMessage::AckError(err)))
+            }
+        },
         LocalMessage::ReqBlockSaveNodeActivation(
             node_activation,
             node_root,
@@ -220,22 +668,259 @@ async fn deal_local_message(
                 )))
             }
         }
-        LocalMessage::ReqBlockPack() => {
-            let res = blockchain_module.pack_block();
-            if res.is_ok() {
-                log::info!("block packed success!");
-            } else {
-                log::error!("block packed fail!");
+        LocalMessage::BlockTick() => {
+            let now = Local::now().timestamp_millis() as u64;
+            match blockchain_module.is_my_slot(now) {
+                Ok(true) => pack_block(blockchain_module, now).await,
+                Ok(false) => {}
+                Err(e) => log::error!("blockchain check slot fail: {:?}", e),
             }
-            let res = blockchain_module
-                .network_caller
-                .notify(Message::LocalMessage(LocalMessage::ReqNodeDistributeTask(
-                    blockchain_module.current_block.header.index,
-                )))
-                .await;
-            log::info!("notify node distribute task");
+            None
+        }
+        LocalMessage::ReqBlockPack() => {
+            pack_block(blockchain_module, Local::now().timestamp_millis() as u64).await;
             None
         }
         _ => None,
     }
 }
+
+async fn pack_block(blockchain_module: &mut BlockchainModule, timestamp: u64) {
+    let res = blockchain_module.pack_block(timestamp).await;
+    if res.is_ok() {
+        log::info!("block packed success!");
+    } else {
+        log::error!("block packed fail!");
+    }
+    let res = blockchain_module
+        .network_caller
+        .notify(Message::LocalMessage(LocalMessage::ReqNodeDistributeTask(
+            blockchain_module.current_block.header.index,
+        )))
+        .await;
+    log::info!("notify node distribute task");
+}
+
+/// `calc_list_root` calculate the trie root of a body list, the SCALE encoded items are keyed by
+/// their hash.
+fn calc_list_root<T: Encode>(
+    db: Arc<dyn KeyValueDB>,
+    column: u32,
+    list: &[T],
+    params: &ChainParams,
+) -> Result<H256> {
+    let mut data = vec![];
+    for item in list {
+        let item_bytes = item.encode();
+        data.push((KeccakHasher::hash(&item_bytes).to_vec(), item_bytes));
+    }
+    let data = data
+        .iter()
+        .map(|(k, v)| (k.as_slice(), v.as_slice()))
+        .collect();
+    Ok(database::calc_root(
+        db,
+        column,
+        data,
+        params.inline_value_threshold,
+    )?)
+}
+
+/// `set_body_roots` fill the `current_*` roots of the header from the block body.
+pub fn set_body_roots(
+    db: Arc<dyn KeyValueDB>,
+    block: &mut Block,
+    params: &ChainParams,
+) -> Result<()> {
+    block.header.current_task_operation_root = calc_list_root(
+        db.clone(),
+        database::db::COL_TASK_OPERATIONS,
+        &block.body.tasks,
+        params,
+    )?;
+    block.header.current_task_result_root = calc_list_root(
+        db.clone(),
+        database::db::COL_TASK_RESULT,
+        &block.body.task_results,
+        params,
+    )?;
+    block.header.current_reward_root = calc_list_root(
+        db.clone(),
+        database::db::COL_ACCOUNT,
+        &block.body.reward,
+        params,
+    )?;
+    block.header.current_node_activation_root = calc_list_root(
+        db,
+        database::db::COL_NODE_LIST_ACTIVATED,
+        &block.body.node_activation,
+        params,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use libp2p::identity::ed25519;
+
+    fn test_genesis(keeper: &ed25519::Keypair) -> GenesisSpec {
+        let keeper = WdnIdentity::from_key_pair(keeper.clone()).sender().unwrap();
+        GenesisSpec {
+            chain_id: "wdn-test".to_owned(),
+            keepers: vec![keeper.to_text()],
+            tasks: vec![],
+            balances: vec![],
+            params: ChainParams {
+                block_time_millis: 1000,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn test_module(
+        db: Arc<dyn KeyValueDB>,
+        key: &ed25519::Keypair,
+        genesis: &GenesisSpec,
+    ) -> BlockchainModule {
+        BlockchainModule::with_genesis(db, Keypair::Ed25519(key.clone()), genesis.clone()).unwrap()
+    }
+
+    #[test]
+    fn test_import_block() {
+        let keeper = ed25519::Keypair::generate();
+        let genesis = test_genesis(&keeper);
+        let mut producer = test_module(database::open_memory_database(), &keeper, &genesis);
+        let mut importer = test_module(
+            database::open_memory_database(),
+            &ed25519::Keypair::generate(),
+            &genesis,
+        );
+        let now = Local::now().timestamp_millis() as u64;
+        task::block_on(producer.pack_block(now)).unwrap();
+        let block = producer.db.get_latest_block().unwrap().unwrap();
+        assert_eq!(block.header.index, 1);
+
+        let mut orphan = block.clone();
+        orphan.header.previous_hash = H256::repeat_byte(1);
+        assert!(task::block_on(importer.import_block(orphan)).is_err());
+
+        let mut forged = block.clone();
+        forged.header.signature[0] ^= 1;
+        assert!(task::block_on(importer.import_block(forged)).is_err());
+
+        let mut non_keeper = block.clone();
+        let identity = WdnIdentity::from_key_pair(ed25519::Keypair::generate());
+        signature::sign_header(&mut non_keeper.header, &identity).unwrap();
+        assert!(task::block_on(importer.import_block(non_keeper)).is_err());
+
+        // signed by the keeper, but the roots don't match the body
+        let mut bad_roots = block.clone();
+        bad_roots.header.account_root = H256::repeat_byte(1);
+        let identity = WdnIdentity::from_key_pair(keeper);
+        signature::sign_header(&mut bad_roots.header, &identity).unwrap();
+        assert!(task::block_on(importer.import_block(bad_roots)).is_err());
+
+        task::block_on(importer.import_block(block.clone())).unwrap();
+        assert_eq!(importer.db.get_latest_block().unwrap(), Some(block));
+        assert_eq!(importer.current_block.header.index, 2);
+    }
+
+    #[test]
+    fn test_update_sync_target() {
+        let keeper = ed25519::Keypair::generate();
+        let genesis = test_genesis(&keeper);
+        let mut module = test_module(database::open_memory_database(), &keeper, &genesis);
+        let mut head = module.current_block.header.clone();
+        head.index = 100;
+
+        // a head not signed by a keeper doesn't stop the block production
+        let identity = WdnIdentity::from_key_pair(ed25519::Keypair::generate());
+        signature::sign_header(&mut head, &identity).unwrap();
+        assert!(module.update_sync_target(&head, None).is_err());
+        assert_eq!(module.sync_status().state, sync::SyncState::CaughtUp);
+
+        signature::sign_header(&mut head, &WdnIdentity::from_key_pair(keeper)).unwrap();
+        let peer = PeerId::random();
+        module.update_sync_target(&head, Some(peer)).unwrap();
+        assert_eq!(module.sync_target.peer(), Some(peer.to_base58().as_str()));
+        let status = module.sync_status();
+        assert_eq!(status.state, sync::SyncState::Syncing);
+        assert_eq!(status.target_height, 101);
+    }
+
+    #[test]
+    fn test_resume_from_head() {
+        let keeper = ed25519::Keypair::generate();
+        let genesis = test_genesis(&keeper);
+        let db = database::open_memory_database();
+        let mut producer = test_module(db.clone(), &keeper, &genesis);
+        let now = Local::now().timestamp_millis() as u64;
+        task::block_on(producer.pack_block(now)).unwrap();
+        task::block_on(producer.pack_block(now + 1000)).unwrap();
+        let head = producer.db.get_latest_block().unwrap().unwrap();
+        assert_eq!(head.header.index, 2);
+        drop(producer);
+
+        let resumed = test_module(db.clone(), &keeper, &genesis);
+        assert_eq!(resumed.db.get_latest_block().unwrap(), Some(head.clone()));
+        assert_eq!(resumed.current_block.header.index, 3);
+        assert_eq!(
+            resumed.current_block.header.previous_hash,
+            head.hash().unwrap()
+        );
+        for (column, root) in [
+            (database::db::COL_ACCOUNT, head.header.account_root),
+            (database::db::COL_TASK_LIST, head.header.task_root),
+            (
+                database::db::COL_TASK_OPERATIONS,
+                head.header.task_operation_root,
+            ),
+            (database::db::COL_TASK_RESULT, head.header.task_result_root),
+            (database::db::COL_NODE_LIST, head.header.node_root),
+            (
+                database::db::COL_NODE_LIST_ACTIVATED,
+                head.header.node_activation_root,
+            ),
+        ] {
+            assert_eq!(database::get_root(&db, column).unwrap(), root);
+        }
+    }
+
+    #[test]
+    fn test_resume_from_snapshot() {
+        let keeper = ed25519::Keypair::generate();
+        let genesis = test_genesis(&keeper);
+        let db = database::open_memory_database();
+        let mut producer = test_module(db.clone(), &keeper, &genesis);
+        let now = Local::now().timestamp_millis() as u64;
+        task::block_on(producer.pack_block(now)).unwrap();
+        task::block_on(producer.pack_block(now + 1000)).unwrap();
+        let head = producer.db.get_latest_block().unwrap().unwrap();
+
+        let snapshot = snapshot::Snapshot::at_block(db, head.header.index).unwrap();
+        let restored_db = database::open_memory_database();
+        snapshot
+            .restore(
+                restored_db.clone(),
+                genesis::genesis_hash(&genesis).unwrap(),
+                &genesis.params,
+            )
+            .unwrap();
+
+        // the restored head is kept, the genesis block is not committed again
+        let resumed = test_module(restored_db.clone(), &keeper, &genesis);
+        assert_eq!(resumed.db.get_latest_block().unwrap(), Some(head.clone()));
+        assert_eq!(resumed.db.get_canonical_hash(0).unwrap(), None);
+        assert_eq!(resumed.current_block.header.index, 3);
+        drop(resumed);
+
+        let mut other_chain = genesis.clone();
+        other_chain.chain_id = "wdn-other".to_owned();
+        assert!(
+            BlockchainModule::with_genesis(restored_db, Keypair::Ed25519(keeper), other_chain)
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use ethereum_types::H256;
+use serde::{Deserialize, Serialize};
+
+use crate::database::{self, AppDB, StateProof};
+
+use super::{db::Header, error::Result, BlockchainModule};
+
+/// `StateTrie` the state tries with their roots recorded in every block header.
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Deserialize, Serialize)]
+pub enum StateTrie {
+    Account,
+    Reward,
+    Task,
+    TaskOperation,
+    TaskResult,
+    Node,
+    NodeActivation,
+}
+
+impl StateTrie {
+    pub fn column(&self) -> u32 {
+        match self {
+            StateTrie::Account | StateTrie::Reward => database::db::COL_ACCOUNT,
+            StateTrie::Task => database::db::COL_TASK_LIST,
+            StateTrie::TaskOperation => database::db::COL_TASK_OPERATIONS,
+            StateTrie::TaskResult => database::db::COL_TASK_RESULT,
+            StateTrie::Node => database::db::COL_NODE_LIST,
+            StateTrie::NodeActivation => database::db::COL_NODE_LIST_ACTIVATED,
+        }
+    }
+
+    pub fn root(&self, header: &Header) -> H256 {
+        match self {
+            StateTrie::Account => header.account_root,
+            StateTrie::Reward => header.reward_root,
+            StateTrie::Task => header.task_root,
+            StateTrie::TaskOperation => header.task_operation_root,
+            StateTrie::TaskResult => header.task_result_root,
+            StateTrie::Node => header.node_root,
+            StateTrie::NodeActivation => header.node_activation_root,
+        }
+    }
+}
+
+/// `HeaderStateProof` a state proof with the block whose header root it's proven against.
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize)]
+pub struct HeaderStateProof {
+    pub index: u64,
+    pub block_hash: H256,
+    pub trie: StateTrie,
+    pub state_proof: StateProof,
+}
+
+impl HeaderStateProof {
+    /// `verify` check the proof against the header, the header must be trusted by the caller.
+    pub fn verify(&self, header: &Header) -> Result<()> {
+        Ok(database::verify_proof(
+            self.trie.root(header),
+            &self.state_proof,
+        )?)
+    }
+}
+
+impl BlockchainModule {
+    /// `get_state_proof` get the values of the keys in the state trie of the head block,
+    /// with a proof against the header root.
+    pub fn get_state_proof(&self, trie: StateTrie, keys: &[Vec<u8>]) -> Result<HeaderStateProof> {
+        let head = match self.db.get_latest_block()? {
+            Some(head) => head,
+            None => return Err("blockchain has no block".to_string().into()),
+        };
+        let app_db = AppDB::new(
+            self.db.db.clone(),
+            trie.column(),
+            trie.root(&head.header).to_fixed_bytes(),
+        )?;
+        Ok(HeaderStateProof {
+            index: head.header.index,
+            block_hash: head.hash()?,
+            trie,
+            state_proof: app_db.get_with_proof(keys)?,
+        })
+    }
+}
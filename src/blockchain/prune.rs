@@ -0,0 +1,327 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use ethereum_types::H256;
+use kvdb::KeyValueDB;
+use parity_scale_codec::Decode;
+
+use crate::database::{self, journal, prune, AppDB};
+
+use super::{
+    db::{get_latest_header, BlockchainDB, Header},
+    error::Result,
+    BlockchainModule,
+};
+
+/// Block indexes whose journals one pruning tick releases at most, a node catching up on a long
+/// history prunes it over several blocks.
+pub const PRUNE_BATCH_BLOCKS: u64 = 16;
+
+/// The lowest block index whose journals are not released yet.
+pub const KEY_PRUNED_HEIGHT: &[u8; 13] = b"pruned_height";
+
+/// `state_tries` the column and root of every state trie recorded in the header.
+pub(crate) fn state_tries(header: &Header) -> [(u32, H256); 7] {
+    [
+        (database::db::COL_ACCOUNT, header.account_root),
+        (database::db::COL_ACCOUNT, header.reward_root),
+        (database::db::COL_TASK_LIST, header.task_root),
+        (
+            database::db::COL_TASK_OPERATIONS,
+            header.task_operation_root,
+        ),
+        (database::db::COL_TASK_RESULT, header.task_result_root),
+        (database::db::COL_NODE_LIST, header.node_root),
+        (
+            database::db::COL_NODE_LIST_ACTIVATED,
+            header.node_activation_root,
+        ),
+    ]
+}
+
+/// `kept_from` the index of the oldest state a pruned node keeps: the states of the last
+/// `history_depth` blocks, and every state from the last finalized block on.
+pub fn kept_from(head_index: u64, history_depth: u64, finalized_height: u64) -> u64 {
+    head_index
+        .saturating_sub(history_depth.saturating_sub(1))
+        .min(finalized_height.saturating_sub(1))
+}
+
+/// `prune_state` delete the trie nodes no longer reachable from the kept states, return the
+/// number of deleted nodes.
+///
+/// It walks every kept state and every stored node, so it's only run by the offline `prune`
+/// command, which also drops the old nodes of the block tries. A running node releases the
+/// journals of the blocks with `prune_journals` instead.
+///
+/// The states of the last `history_depth` blocks are kept, and so is every state from the last
+/// finalized block on, since a fork above it may still be replayed from its ancestor. Side blocks
+/// above the kept index keep their state, a child of theirs may still be imported.
+pub fn prune_state(db: Arc<dyn KeyValueDB>, history_depth: u64) -> Result<usize> {
+    let blockchain_db = BlockchainDB::new(db.clone())?;
+    let head = match blockchain_db.get_latest_block()? {
+        Some(head) => head,
+        None => return Ok(0),
+    };
+    let keep_from = kept_from(
+        head.header.index,
+        history_depth,
+        blockchain_db.get_finalized_height()?,
+    );
+    if keep_from == 0 {
+        return Ok(0);
+    }
+
+    // Mark the nodes of the kept states, the block tries are kept at their latest roots.
+    let mut keep: HashMap<u32, HashSet<Vec<u8>>> = HashMap::new();
+    for column in [
+        database::db::COL_BLOCK_HEADERS,
+        database::db::COL_BLOCK_BODIES,
+    ] {
+        let root = database::get_root(&db, column)?;
+        AppDB::new(db.clone(), column, root.to_fixed_bytes())?
+            .collect_node_keys(keep.entry(column).or_default())?;
+    }
+    for (_, header_bytes) in blockchain_db.header_db.list(&[], None, usize::MAX)? {
+        let header: Header = serde_cbor::from_slice(&header_bytes)?;
+        if header.index < keep_from {
+            continue;
+        }
+        for (column, root) in state_tries(&header) {
+            AppDB::new(db.clone(), column, root.to_fixed_bytes())?
+                .collect_node_keys(keep.entry(column).or_default())?;
+        }
+    }
+
+    let mut deleted = 0;
+    for (column, keep) in &keep {
+        deleted += prune::sweep_column(&db, *column, keep)?;
+    }
+    log::info!(
+        "prune state before block {}, {} trie nodes deleted",
+        keep_from,
+        deleted
+    );
+    Ok(deleted)
+}
+
+/// `set_journaling` journal the state trie nodes on a pruned node only, an archive node never
+/// releases them.
+///
+/// A node switched from archive to pruned journals the blocks from the next one on, the nodes
+/// written before have no references and are only dropped by the offline `prune` command. A node
+/// switched back to archive drops the references and the journals.
+pub fn set_journaling(db: &Arc<dyn KeyValueDB>, pruned: bool) -> Result<()> {
+    if journal::is_enabled(db.as_ref())? == pruned {
+        return Ok(());
+    }
+    let mut tx = db.transaction();
+    if pruned {
+        let next_index = match get_latest_header(db.clone())? {
+            Some(head) => head.index + 1,
+            None => 0,
+        };
+        tx.put(
+            database::db::COL_EXTRA,
+            journal::KEY_JOURNAL_ENABLED,
+            &[1u8],
+        );
+        tx.put(
+            database::db::COL_EXTRA,
+            KEY_PRUNED_HEIGHT,
+            &serde_cbor::to_vec(&next_index)?,
+        );
+        log::info!("journal the state from block {}", next_index);
+    } else {
+        tx.delete(database::db::COL_EXTRA, journal::KEY_JOURNAL_ENABLED);
+        tx.delete(database::db::COL_EXTRA, KEY_PRUNED_HEIGHT);
+        tx.delete_prefix(database::db::COL_EXTRA, journal::KEY_NODE_REFS);
+        tx.delete_prefix(database::db::COL_EXTRA, journal::KEY_JOURNAL);
+        log::info!("stop journaling the state");
+    }
+    db.write(tx)?;
+    Ok(())
+}
+
+/// `get_pruned_height` the lowest block index whose journals are not released yet.
+pub fn get_pruned_height(db: &Arc<dyn KeyValueDB>) -> Result<u64> {
+    match db.get(database::db::COL_EXTRA, KEY_PRUNED_HEIGHT)? {
+        Some(height) => Ok(serde_cbor::from_slice(&height)?),
+        None => Ok(0),
+    }
+}
+
+/// `prune_journals` release the journals of the blocks below the kept index, at most
+/// `max_blocks` indexes from the pruned height on, return the number of deleted nodes.
+///
+/// A canonical block releases the nodes it removed from the state of its parent, a side block
+/// releases the nodes it inserted. A node is deleted once no kept state references it, so a
+/// tick costs the size of the released journals, not the size of the chain or the state.
+pub fn prune_journals(
+    blockchain_db: &BlockchainDB,
+    history_depth: u64,
+    max_blocks: u64,
+) -> Result<usize> {
+    let db = &blockchain_db.db;
+    let head = match blockchain_db.get_latest_block()? {
+        Some(head) => head,
+        None => return Ok(0),
+    };
+    let keep_from = kept_from(
+        head.header.index,
+        history_depth,
+        blockchain_db.get_finalized_height()?,
+    );
+    let pruned_height = get_pruned_height(db)?;
+    let end = keep_from.min(pruned_height.saturating_add(max_blocks));
+    if end <= pruned_height {
+        return Ok(0);
+    }
+
+    let mut tx = db.transaction();
+    let mut released: HashMap<(u32, Vec<u8>), u32> = HashMap::new();
+    for index in pruned_height..end {
+        let canonical_hash = blockchain_db.get_canonical_hash(index)?;
+        let prefix = journal::journal_prefix(index);
+        for (key, value) in db.iter_with_prefix(database::db::COL_EXTRA, &prefix) {
+            let node_journal = journal::NodeJournal::decode(&mut value.as_ref())?;
+            let hash = H256::from_slice(&key[prefix.len()..]);
+            let nodes = if Some(hash) == canonical_hash {
+                node_journal.removed
+            } else {
+                node_journal.inserted
+            };
+            for (column, node_key, count) in nodes {
+                *released.entry((column, node_key)).or_default() += count;
+            }
+            tx.delete(database::db::COL_EXTRA, &key);
+        }
+    }
+    let deleted = journal::release_nodes(db.as_ref(), &mut tx, &released)?;
+    tx.put(
+        database::db::COL_EXTRA,
+        KEY_PRUNED_HEIGHT,
+        &serde_cbor::to_vec(&end)?,
+    );
+    db.write(tx)?;
+    log::info!(
+        "prune journals of blocks {} to {}, {} trie nodes deleted",
+        pruned_height,
+        end - 1,
+        deleted
+    );
+    Ok(deleted)
+}
+
+impl BlockchainModule {
+    /// `prune_tick` release the journals of the blocks no longer kept on a pruned node.
+    pub fn prune_tick(&self) {
+        let history_depth = match self.prune_depth {
+            Some(depth) => depth,
+            None => return,
+        };
+        if let Err(e) = prune_journals(&self.db, history_depth, PRUNE_BATCH_BLOCKS) {
+            log::error!("prune journals fail: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::blockchain::{
+        history::StateView,
+        test_chain::{add_task_body, TestChain},
+    };
+
+    #[test]
+    fn test_prune_state() {
+        let db = database::open_memory_database();
+        let mut chain = TestChain::build(db.clone(), 4);
+        chain.blockchain_db.set_finalized_height(4).unwrap();
+        let blocks = chain.blocks;
+
+        assert!(prune_state(db.clone(), 2).unwrap() > 0);
+        let head_state = StateView::open(db.clone(), blocks[3].header.clone()).unwrap();
+        assert_eq!(head_state.list_tasks(None, 10).unwrap().len(), 4);
+        let kept_state = StateView::open(db.clone(), blocks[2].header.clone()).unwrap();
+        assert_eq!(kept_state.list_tasks(None, 10).unwrap().len(), 3);
+        let pruned_state = StateView::open(db, blocks[0].header.clone()).unwrap();
+        assert!(pruned_state.list_tasks(None, 10).is_err());
+    }
+
+    #[test]
+    fn test_set_journaling() {
+        // an archive node writes no references or journals
+        let db = database::open_memory_database();
+        let mut chain = TestChain::build(db.clone(), 2);
+        for prefix in [&journal::KEY_NODE_REFS[..], &journal::KEY_JOURNAL[..]] {
+            assert!(db
+                .iter_with_prefix(database::db::COL_EXTRA, prefix)
+                .next()
+                .is_none());
+        }
+
+        // a node switched to pruned journals the blocks from the next one on
+        set_journaling(&db, true).unwrap();
+        assert_eq!(get_pruned_height(&db).unwrap(), 2);
+        chain.push_block(add_task_body(2), &[]);
+        assert!(db
+            .iter_with_prefix(database::db::COL_EXTRA, &journal::journal_prefix(2))
+            .next()
+            .is_some());
+
+        set_journaling(&db, false).unwrap();
+        for prefix in [&journal::KEY_NODE_REFS[..], &journal::KEY_JOURNAL[..]] {
+            assert!(db
+                .iter_with_prefix(database::db::COL_EXTRA, prefix)
+                .next()
+                .is_none());
+        }
+    }
+
+    #[test]
+    fn test_prune_journals() {
+        let db = database::open_memory_database();
+        set_journaling(&db, true).unwrap();
+        let mut chain = TestChain::build(db.clone(), 4);
+        let genesis = chain.blocks[0].clone();
+        let side_block = chain.push_side_block(&genesis, add_task_body(7));
+        chain.blockchain_db.set_finalized_height(4).unwrap();
+        let blocks = chain.blocks.clone();
+
+        // the states of the blocks 0 and 1 are released, the state of block 1 is the base of
+        // the kept block 2, so only the nodes block 1 removed from the state 0 are deleted
+        assert!(prune_journals(&chain.blockchain_db, 2, PRUNE_BATCH_BLOCKS).unwrap() > 0);
+        assert_eq!(get_pruned_height(&db).unwrap(), 2);
+        assert!(db
+            .iter_with_prefix(database::db::COL_EXTRA, &journal::journal_prefix(1))
+            .next()
+            .is_none());
+        let head_state = StateView::open(db.clone(), blocks[3].header.clone()).unwrap();
+        assert_eq!(head_state.list_tasks(None, 10).unwrap().len(), 4);
+        let kept_state = StateView::open(db.clone(), blocks[1].header.clone()).unwrap();
+        assert_eq!(kept_state.list_tasks(None, 10).unwrap().len(), 2);
+        let pruned_state = StateView::open(db.clone(), blocks[0].header.clone()).unwrap();
+        assert!(pruned_state.list_tasks(None, 10).is_err());
+        let side_state = StateView::open(db.clone(), side_block.header.clone()).unwrap();
+        assert!(side_state.list_tasks(None, 10).is_err());
+
+        // nothing more to release until the head moves
+        assert_eq!(
+            prune_journals(&chain.blockchain_db, 2, PRUNE_BATCH_BLOCKS).unwrap(),
+            0
+        );
+        chain.push_block(add_task_body(4), &[]);
+        chain.blockchain_db.set_finalized_height(5).unwrap();
+        assert!(prune_journals(&chain.blockchain_db, 2, PRUNE_BATCH_BLOCKS).unwrap() > 0);
+        assert_eq!(get_pruned_height(&db).unwrap(), 3);
+        let head_state = StateView::open(db.clone(), blocks[3].header.clone()).unwrap();
+        assert_eq!(head_state.list_tasks(None, 10).unwrap().len(), 4);
+        let pruned_state = StateView::open(db, blocks[1].header.clone()).unwrap();
+        assert!(pruned_state.list_tasks(None, 10).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use crate::{
+    database::data_types::{NodeActiveStatus, NodeData, TaskData, TaskStatus},
+    node::db::NodeDB,
+    task::db::TaskDB,
+};
+
+use super::{
+    db::{Reward, TaskResult},
+    error::Result,
+};
+
+/// Default total reward distributed in every block.
+pub const BLOCK_REWARD: u64 = 10_000;
+/// Online blocks above this value don't raise the reward any more.
+pub const MAX_ONLINE_BLOCKS: u128 = 100;
+
+/// `accepted_results` the task and worker of every accepted task result.
+///
+/// A result is accepted when its task is enabled and its worker is activated, a worker gets
+/// one accepted result per task in a block.
+fn accepted_results(
+    task_db: &TaskDB,
+    node_db: &NodeDB,
+    task_results: &[TaskResult],
+) -> Result<Vec<(TaskData, NodeData)>> {
+    let mut seen: HashSet<(u64, String)> = HashSet::new();
+    let mut accepted = vec![];
+    for task_result in task_results {
+        if !seen.insert((task_result.id, task_result.peer_id.clone())) {
+            continue;
+        }
+        let task = match task_db.get_task(task_result.id)? {
+            Some(task) if task.status == TaskStatus::Enable => task,
+            _ => continue,
+        };
+        let node = match node_db.get_node(&task_result.peer_id)? {
+            Some(node) if node.active_status == NodeActiveStatus::Actived => node,
+            _ => continue,
+        };
+        accepted.push((task, node));
+    }
+    Ok(accepted)
+}
+
+/// `online_workers` the peer ids of the workers with an accepted task result, their
+/// `online_blocks` grows by one with the block.
+pub fn online_workers(
+    task_db: &TaskDB,
+    node_db: &NodeDB,
+    task_results: &[TaskResult],
+) -> Result<BTreeSet<String>> {
+    Ok(accepted_results(task_db, node_db, task_results)?
+        .into_iter()
+        .map(|(_, node)| node.peer_id)
+        .collect())
+}
+
+/// `calc_rewards` distribute the block reward among the workers of the accepted task results.
+///
+/// Every accepted result scores the task `reward_weight` multiplied by
+/// `min(online_blocks, MAX_ONLINE_BLOCKS) + 1`, then every account gets its share of
+/// `block_reward` rounded down. Rewards are sorted by account.
+pub fn calc_rewards(
+    task_db: &TaskDB,
+    node_db: &NodeDB,
+    task_results: &[TaskResult],
+    block_reward: u64,
+) -> Result<Vec<Reward>> {
+    let mut account_scores: BTreeMap<Vec<u8>, u128> = BTreeMap::new();
+    let mut total_score: u128 = 0;
+
+    for (task, node) in accepted_results(task_db, node_db, task_results)? {
+        let score = task.reward_weight as u128 * (node.online_blocks.min(MAX_ONLINE_BLOCKS) + 1);
+        let account = serde_cbor::to_vec(&node.bind_address)?;
+        *account_scores.entry(account).or_insert(0) += score;
+        total_score += score;
+    }
+
+    if total_score == 0 {
+        return Ok(vec![]);
+    }
+
+    let mut rewards = vec![];
+    for (account, score) in account_scores {
+        let amount = (block_reward as u128 * score / total_score) as u64;
+        if amount > 0 {
+            rewards.push(Reward { account, amount });
+        }
+    }
+    Ok(rewards)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ethereum_types::H256;
+
+    use crate::database::{
+        self,
+        data_types::{NodeData, TaskData, TaskType},
+    };
+
+    fn task_result(id: u64, peer_id: &str) -> TaskResult {
+        TaskResult {
+            id,
+            peer_id: peer_id.to_owned(),
+            timestamp: 0,
+            result: vec![],
+        }
+    }
+
+    #[test]
+    fn test_calc_rewards() {
+        let db = database::open_memory_database();
+        let mut task_db = TaskDB::new(db.clone()).unwrap();
+        let mut node_db = NodeDB::new(db).unwrap();
+
+        for (id, reward_weight) in [(1, 100), (2, 300)] {
+            task_db
+                .insert_task(TaskData {
+                    id,
+                    hash: H256::zero(),
+                    task_type: TaskType::LongTerm,
+                    node_limit: 10,
+                    current_node_num: 0,
+                    status: TaskStatus::Enable,
+                    reward_weight,
+                })
+                .unwrap();
+        }
+        for peer_id in ["worker_one", "worker_two", "worker_inactived"] {
+            let mut node = NodeData::default();
+            node.peer_id = peer_id.to_owned();
+            node.bind_address = peer_id.to_owned();
+            if peer_id != "worker_inactived" {
+                node.active_status = NodeActiveStatus::Actived;
+            }
+            node_db.insert_node(node).unwrap();
+        }
+
+        let rewards = calc_rewards(
+            &task_db,
+            &node_db,
+            &[
+                task_result(1, "worker_one"),
+                task_result(1, "worker_one"),
+                task_result(2, "worker_two"),
+                task_result(2, "worker_inactived"),
+                task_result(3, "worker_one"),
+            ],
+            BLOCK_REWARD,
+        )
+        .unwrap();
+
+        assert_eq!(rewards.len(), 2);
+        let reward_of = |account: &str| {
+            let account = serde_cbor::to_vec(&account.to_owned()).unwrap();
+            rewards
+                .iter()
+                .find(|r| r.account == account)
+                .unwrap()
+                .amount
+        };
+        assert_eq!(reward_of("worker_one"), BLOCK_REWARD / 4);
+        assert_eq!(reward_of("worker_two"), BLOCK_REWARD * 3 / 4);
+    }
+}
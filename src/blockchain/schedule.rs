@@ -0,0 +1,65 @@
+use ic_agent::ic_types::Principal;
+
+use crate::ic::canister::node::Keeper;
+
+/// Interval between two block ticks, a keeper checks its slot on every tick.
+pub const BLOCK_TICK_INTERVAL_MILLIS: u64 = 500;
+
+/// `sorted_keepers` order the keeper set, every node must get the same producer schedule.
+pub fn sorted_keepers(keepers: &Keeper) -> Vec<Principal> {
+    let mut keepers: Vec<Principal> = keepers.iter().cloned().collect();
+    keepers.sort_by(|a, b| a.as_slice().cmp(b.as_slice()));
+    keepers
+}
+
+/// `slot_offset` the number of slots passed since the parent block, the first slot after the
+/// parent is 1. Return none while the first slot is not reached.
+pub fn slot_offset(parent_timestamp: u64, timestamp: u64, block_time_millis: u64) -> Option<u64> {
+    if block_time_millis == 0 || timestamp < parent_timestamp {
+        return None;
+    }
+    match (timestamp - parent_timestamp) / block_time_millis {
+        0 => None,
+        offset => Some(offset),
+    }
+}
+
+/// `slot_producer` the keeper allowed to pack the block of the index in the slot.
+///
+/// Keepers take turns by block index, when the producer of the first slot misses it, every
+/// following slot passes to the next keeper.
+pub fn slot_producer(keepers: &[Principal], index: u64, slot_offset: u64) -> Option<Principal> {
+    if keepers.is_empty() || slot_offset == 0 {
+        return None;
+    }
+    let position = (index + slot_offset - 1) % keepers.len() as u64;
+    Some(keepers[position as usize].clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slot_offset() {
+        assert_eq!(slot_offset(1000, 1500, 1000), None);
+        assert_eq!(slot_offset(1000, 2000, 1000), Some(1));
+        assert_eq!(slot_offset(1000, 3999, 1000), Some(2));
+        assert_eq!(slot_offset(1000, 500, 1000), None);
+    }
+
+    #[test]
+    fn test_slot_producer() {
+        let keepers = vec![
+            Principal::from_slice(&[1]),
+            Principal::from_slice(&[2]),
+            Principal::from_slice(&[3]),
+        ];
+
+        assert_eq!(slot_producer(&keepers, 1, 1), Some(keepers[1].clone()));
+        assert_eq!(slot_producer(&keepers, 2, 1), Some(keepers[2].clone()));
+        // missed slot passes to the next keeper
+        assert_eq!(slot_producer(&keepers, 2, 2), Some(keepers[0].clone()));
+        assert_eq!(slot_producer(&[], 2, 1), None);
+    }
+}
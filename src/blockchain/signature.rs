@@ -0,0 +1,81 @@
+use ic_agent::Identity;
+use parity_scale_codec::Encode;
+
+use crate::ic::{
+    canister::node::Keeper,
+    wdn_identity::{self, WdnIdentity},
+};
+
+use super::db::Header;
+use super::error::Result;
+
+/// `signing_bytes` is the canonical header encoding covered by the producer signature,
+/// it's the SCALE encoded header with an empty `signature` field.
+pub fn signing_bytes(header: &Header) -> Result<Vec<u8>> {
+    let mut unsigned_header = header.clone();
+    unsigned_header.signature = vec![];
+    Ok(unsigned_header.encode())
+}
+
+/// `sign_header` fill the `minter` with the DER encoded public key of the producer,
+/// then sign the header with the producer identity.
+pub fn sign_header(header: &mut Header, identity: &WdnIdentity) -> Result<()> {
+    header.minter = identity.get_der_encoded_public_key();
+    let header_bytes = signing_bytes(header)?;
+    let signature = identity.sign(&header_bytes)?.signature;
+    match signature {
+        Some(signature) => header.signature = signature,
+        None => return Err("sign block header fail".to_string().into()),
+    }
+    Ok(())
+}
+
+/// `verify_header` check the header was signed by its minter and the minter is a keeper.
+pub fn verify_header(header: &Header, keepers: &Keeper) -> Result<()> {
+    let public_key = match wdn_identity::public_key_from_der(&header.minter) {
+        Some(k) => k,
+        None => return Err("invalid block minter".to_string().into()),
+    };
+
+    let principal = wdn_identity::principal_from_der(&header.minter);
+    if !keepers.contains(&principal) {
+        return Err(format!("block minter {} is not a keeper", principal.to_text()).into());
+    }
+
+    let header_bytes = signing_bytes(header)?;
+    if !public_key.verify(&header_bytes, &header.signature) {
+        return Err("invalid block signature".to_string().into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use libp2p::identity::ed25519::Keypair;
+
+    #[test]
+    fn test_sign_and_verify_header() {
+        let identity = WdnIdentity::from_key_pair(Keypair::generate());
+        let mut keepers = Keeper::new();
+        keepers.insert(identity.sender().unwrap());
+
+        let mut header = Header::default();
+        header.index = 1;
+        sign_header(&mut header, &identity).unwrap();
+        assert!(verify_header(&header, &keepers).is_ok());
+
+        header.index = 2;
+        assert!(verify_header(&header, &keepers).is_err(), "tampered header");
+    }
+
+    #[test]
+    fn test_reject_non_keeper() {
+        let identity = WdnIdentity::from_key_pair(Keypair::generate());
+        let mut header = Header::default();
+        sign_header(&mut header, &identity).unwrap();
+
+        assert!(verify_header(&header, &Keeper::new()).is_err());
+    }
+}
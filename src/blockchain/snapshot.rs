@@ -0,0 +1,234 @@
+use std::{fs, sync::Arc};
+
+use ethereum_types::H256;
+use hash_db::Hasher;
+use keccak_hasher::KeccakHasher;
+use kvdb::KeyValueDB;
+use serde::{Deserialize, Serialize};
+
+use crate::database::{self, overlay::OverlayDB, AppDB};
+
+use super::{
+    db::{get_latest_hash, get_latest_header, Block, BlockchainDB, HeadUpdate, KEY_GENESIS_HASH},
+    error::Result,
+    genesis::{self, ChainParams, GenesisSpec},
+    prune::{state_tries, KEY_PRUNED_HEIGHT},
+};
+
+/// `Snapshot` the state of a block, the entries of every state trie in the order of
+/// `state_tries`. The body is kept so the block hash can be checked on import, the genesis
+/// hash tells the chain of the block.
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize)]
+pub struct Snapshot {
+    pub genesis_hash: H256,
+    pub block: Block,
+    pub tries: Vec<Vec<(Vec<u8>, Vec<u8>)>>,
+}
+
+/// `SnapshotFile` the encoded snapshot with the keccak hash of its bytes.
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize)]
+struct SnapshotFile {
+    checksum: H256,
+    snapshot: Vec<u8>,
+}
+
+impl Snapshot {
+    /// `at_block` read the state of the canonical block at the index.
+    pub fn at_block(db: Arc<dyn KeyValueDB>, index: u64) -> Result<Snapshot> {
+        let blockchain_db = BlockchainDB::new(db.clone())?;
+        let genesis_hash = match blockchain_db.get_genesis_hash()? {
+            Some(hash) => hash,
+            None => return Err("no genesis block to export".to_string().into()),
+        };
+        let block = blockchain_db.get_block_by_index(index)?;
+        let mut tries = vec![];
+        for (column, root) in state_tries(&block.header) {
+            let app_db = AppDB::new(db.clone(), column, root.to_fixed_bytes())?;
+            tries.push(app_db.list(&[], None, usize::MAX)?);
+        }
+        Ok(Snapshot {
+            genesis_hash,
+            block,
+            tries,
+        })
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let snapshot = serde_cbor::to_vec(self)?;
+        Ok(serde_cbor::to_vec(&SnapshotFile {
+            checksum: H256(KeccakHasher::hash(&snapshot)),
+            snapshot,
+        })?)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Snapshot> {
+        let file: SnapshotFile = serde_cbor::from_slice(data)?;
+        if H256(KeccakHasher::hash(&file.snapshot)) != file.checksum {
+            return Err("snapshot checksum mismatch".to_string().into());
+        }
+        Ok(serde_cbor::from_slice(&file.snapshot)?)
+    }
+
+    /// `restore` rebuild the state tries, check them against the roots of the block header, then
+    /// write the state and the block as the head of an empty database in one transaction.
+    ///
+    /// The ancestors of the block are not restored, the block is marked final and the genesis
+    /// hash is recorded for the genesis check of the node.
+    pub fn restore(
+        &self,
+        db: Arc<dyn KeyValueDB>,
+        genesis_hash: H256,
+        params: &ChainParams,
+    ) -> Result<()> {
+        if get_latest_hash(db.clone())?.is_some() {
+            return Err("snapshot restore needs an empty database"
+                .to_string()
+                .into());
+        }
+        if self.genesis_hash != genesis_hash {
+            return Err(format!(
+                "snapshot genesis hash mismatch, snapshot {:?}, spec {:?}",
+                self.genesis_hash, genesis_hash
+            )
+            .into());
+        }
+
+        let overlay = OverlayDB::new(db.clone());
+        let header = &self.block.header;
+        let tries = state_tries(header);
+        if tries.len() != self.tries.len() {
+            return Err("snapshot trie count mismatch".to_string().into());
+        }
+        for ((column, root), entries) in tries.into_iter().zip(&self.tries) {
+            let mut app_db = AppDB::new(overlay.clone(), column, [0u8; 32])?;
+            app_db.set_inline_threshold(params.inline_value_threshold)?;
+            for (key, value) in entries {
+                app_db.insert(key, value)?;
+            }
+            // a zero root in the header is the empty trie
+            let expected = AppDB::new(db.clone(), column, root.to_fixed_bytes())?.get_root();
+            if app_db.get_root() != expected {
+                return Err(format!("snapshot state root mismatch in column {}", column).into());
+            }
+            app_db.commit()?;
+        }
+
+        let mut tx = overlay.transaction();
+        tx.put(
+            database::db::COL_EXTRA,
+            KEY_GENESIS_HASH,
+            genesis_hash.as_bytes(),
+        );
+        // the blocks below the snapshot were never journaled
+        tx.put(
+            database::db::COL_EXTRA,
+            KEY_PRUNED_HEIGHT,
+            &serde_cbor::to_vec(&header.index)?,
+        );
+        overlay.write(tx)?;
+
+        let mut blockchain_db = BlockchainDB::new(db)?;
+        blockchain_db.commit_block(
+            &self.block,
+            Some(&overlay),
+            Some(&HeadUpdate {
+                branch: vec![self.block.clone()],
+                old_head_index: None,
+            }),
+        )?;
+        blockchain_db.set_finalized_height(header.index + 1)?;
+        Ok(())
+    }
+}
+
+/// `export_snapshot` write the snapshot of the canonical block at the index to the file, the
+/// head block by default. Return the index of the exported block.
+pub fn export_snapshot(db: Arc<dyn KeyValueDB>, index: Option<u64>, path: &str) -> Result<u64> {
+    let index = match index {
+        Some(index) => index,
+        None => match get_latest_header(db.clone())? {
+            Some(header) => header.index,
+            None => return Err("no block to export".to_string().into()),
+        },
+    };
+    let snapshot = Snapshot::at_block(db, index)?;
+    fs::write(path, snapshot.encode()?)?;
+    log::info!("snapshot of block {} exported to {}", index, path);
+    Ok(index)
+}
+
+/// `import_snapshot` verify the snapshot file is of the chain of the genesis spec and restore it
+/// into the empty database.
+pub fn import_snapshot(db: Arc<dyn KeyValueDB>, path: &str, spec: &GenesisSpec) -> Result<u64> {
+    let snapshot = Snapshot::decode(&fs::read(path)?)?;
+    snapshot.restore(db, genesis::genesis_hash(spec)?, &spec.params)?;
+    log::info!(
+        "snapshot of block {} imported from {}",
+        snapshot.block.header.index,
+        path
+    );
+    Ok(snapshot.block.header.index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::blockchain::{
+        history::StateView,
+        test_chain::{add_task_body, TestChain},
+    };
+
+    #[test]
+    fn test_snapshot_restore() {
+        let db = database::open_memory_database();
+        let mut chain = TestChain::build(db.clone(), 1);
+        let genesis_hash = chain.blocks[0].hash().unwrap();
+        let block = chain.push_block(add_task_body(1), &[(b"account", 10)]);
+
+        let data = Snapshot::at_block(db, 1).unwrap().encode().unwrap();
+        let snapshot = Snapshot::decode(&data).unwrap();
+        assert_eq!(snapshot.genesis_hash, genesis_hash);
+        let restored_db = database::open_memory_database();
+        assert!(snapshot
+            .restore(
+                restored_db.clone(),
+                H256::repeat_byte(1),
+                &ChainParams::default()
+            )
+            .is_err());
+        snapshot
+            .restore(restored_db.clone(), genesis_hash, &ChainParams::default())
+            .unwrap();
+        let state = StateView::open(restored_db.clone(), block.header.clone()).unwrap();
+        assert_eq!(state.list_tasks(None, 10).unwrap().len(), 2);
+        assert_eq!(state.get_balance(b"account").unwrap(), 10);
+        assert!(snapshot
+            .restore(restored_db.clone(), genesis_hash, &ChainParams::default())
+            .is_err());
+
+        // the reopened chain resumes from the restored block
+        let blockchain_db = BlockchainDB::new(restored_db).unwrap();
+        assert_eq!(blockchain_db.get_latest_block().unwrap(), Some(block));
+        assert_eq!(
+            blockchain_db.get_genesis_hash().unwrap(),
+            Some(genesis_hash)
+        );
+        assert_eq!(blockchain_db.get_finalized_height().unwrap(), 2);
+
+        // a tampered snapshot is refused
+        let mut tampered = snapshot;
+        tampered.tries[0].clear();
+        assert!(tampered
+            .restore(
+                database::open_memory_database(),
+                genesis_hash,
+                &ChainParams::default()
+            )
+            .is_err());
+        let mut data = data;
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(Snapshot::decode(&data).is_err());
+    }
+}
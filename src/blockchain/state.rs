@@ -0,0 +1,366 @@
+use std::sync::Arc;
+
+use ethereum_types::H256;
+use kvdb::KeyValueDB;
+use libp2p::identity::PublicKey;
+use parity_scale_codec::Encode;
+
+use crate::{
+    database::data_types::{NodeActiveStatus, NodeData, NodeType, TaskData, TaskStatus},
+    node::db::NodeDB,
+    task::db::TaskDB,
+};
+
+use super::{
+    account::AccountDB,
+    db::{
+        ActivationOperation, Block, Body, Header, NeedSignData, NodeActivation, Reward,
+        TaskOperation, TaskOperationType, TaskResult,
+    },
+    error::Result,
+    genesis::ChainParams,
+    reward,
+};
+
+/// Voting rights granted to a verify node on its activation.
+pub const DEFAULT_VOTING_RIGHTS: u128 = 1;
+
+/// `StateRoots` the state roots recorded in every block header.
+#[derive(Debug, PartialEq, Clone, Eq, Default)]
+pub struct StateRoots {
+    pub account_root: H256,
+    pub reward_root: H256,
+    pub task_root: H256,
+    pub task_operation_root: H256,
+    pub task_result_root: H256,
+    pub node_root: H256,
+    pub node_activation_root: H256,
+}
+
+impl StateRoots {
+    pub fn from_header(header: &Header) -> Self {
+        StateRoots {
+            account_root: header.account_root,
+            reward_root: header.reward_root,
+            task_root: header.task_root,
+            task_operation_root: header.task_operation_root,
+            task_result_root: header.task_result_root,
+            node_root: header.node_root,
+            node_activation_root: header.node_activation_root,
+        }
+    }
+
+    pub fn write_to_header(&self, header: &mut Header) {
+        header.account_root = self.account_root;
+        header.reward_root = self.reward_root;
+        header.task_root = self.task_root;
+        header.task_operation_root = self.task_operation_root;
+        header.task_result_root = self.task_result_root;
+        header.node_root = self.node_root;
+        header.node_activation_root = self.node_activation_root;
+    }
+}
+
+/// `StateTransition` replay a block body on the state of its parent block, the result roots
+/// only depend on the parent roots and the body.
+pub struct StateTransition {
+    task_db: TaskDB,
+    node_db: NodeDB,
+    account_db: AccountDB,
+    block_reward: u64,
+}
+
+impl StateTransition {
+    pub fn new(
+        db: Arc<dyn KeyValueDB>,
+        parent_roots: &StateRoots,
+        params: &ChainParams,
+    ) -> Result<Self> {
+        let mut task_db = TaskDB::with_roots(
+            db.clone(),
+            parent_roots.task_root,
+            parent_roots.task_operation_root,
+            parent_roots.task_result_root,
+        )?;
+        task_db.set_inline_threshold(params.inline_value_threshold)?;
+        let mut node_db = NodeDB::with_roots(
+            db.clone(),
+            parent_roots.node_root,
+            parent_roots.node_activation_root,
+        )?;
+        node_db.set_inline_threshold(params.inline_value_threshold)?;
+        let mut account_db =
+            AccountDB::new(db, parent_roots.account_root, parent_roots.reward_root)?;
+        account_db.set_inline_threshold(params.inline_value_threshold)?;
+
+        Ok(StateTransition {
+            task_db,
+            node_db,
+            account_db,
+            block_reward: params.block_reward,
+        })
+    }
+
+    /// `calc_rewards` calculate the rewards of the task results on the current state.
+    pub fn calc_rewards(&self, task_results: &[TaskResult]) -> Result<Vec<Reward>> {
+        reward::calc_rewards(
+            &self.task_db,
+            &self.node_db,
+            task_results,
+            self.block_reward,
+        )
+    }
+
+    /// `add_balance` credit the account out of any block, only used by the genesis state.
+    pub fn add_balance(&mut self, account: &[u8], amount: u64) -> Result<()> {
+        self.account_db.add_balance(account, amount)
+    }
+
+    /// `commit` write the trie changes of the state to the database, one write per trie.
+    pub fn commit(&mut self) -> Result<()> {
+        self.task_db.commit()?;
+        self.node_db.commit()?;
+        self.account_db.commit()
+    }
+
+    /// `apply_block` check the block rewards against the task results, then apply the body.
+    pub fn apply_block(&mut self, block: &Block) -> Result<()> {
+        if self.calc_rewards(&block.body.task_results)? != block.body.reward {
+            return Err("block reward mismatch".to_string().into());
+        }
+        self.apply_body(block.header.index, &block.body)
+    }
+
+    /// `apply_body` apply task operations, node activations, task results then rewards, and
+    /// count the block as online for the workers of the accepted results.
+    pub fn apply_body(&mut self, index: u64, body: &Body) -> Result<()> {
+        // results are accepted on the parent state, like their rewards
+        let online_workers =
+            reward::online_workers(&self.task_db, &self.node_db, &body.task_results)?;
+        for task_operation in &body.tasks {
+            self.apply_task_operation(task_operation)?;
+        }
+        for node_activation in &body.node_activation {
+            self.apply_node_activation(node_activation)?;
+        }
+        for task_result in &body.task_results {
+            self.task_db.insert_task_result(task_result.clone())?;
+        }
+        for reward in &body.reward {
+            self.account_db.insert_reward(index, reward)?;
+        }
+        for peer_id in online_workers {
+            if let Some(mut node) = self.node_db.get_node(&peer_id)? {
+                node.online_blocks += 1;
+                self.node_db.insert_node(node)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn roots(&self) -> StateRoots {
+        StateRoots {
+            account_root: self.account_db.get_root(),
+            reward_root: self.account_db.get_reward_root(),
+            task_root: H256(self.task_db.task_db.get_root()),
+            task_operation_root: H256(self.task_db.task_operation_db.get_root()),
+            task_result_root: H256(self.task_db.task_result_db.get_root()),
+            node_root: H256(self.node_db.node_db.get_root()),
+            node_activation_root: H256(self.node_db.node_active_db.get_root()),
+        }
+    }
+
+    fn apply_task_operation(&mut self, task_operation: &TaskOperation) -> Result<()> {
+        let task = self.task_db.get_task(task_operation.id)?;
+        match (&task_operation.operation, task) {
+            (TaskOperationType::Add, None) => {
+                self.task_db.insert_task(TaskData {
+                    id: task_operation.id,
+                    hash: task_operation.binary_hash,
+                    task_type: task_operation.task_type.clone(),
+                    node_limit: task_operation.node_limit,
+                    current_node_num: 0,
+                    status: TaskStatus::Enable,
+                    reward_weight: task_operation.reward_weight,
+                })?;
+            }
+            (TaskOperationType::Remove, Some(_)) => {
+                self.task_db.remove_task(task_operation.id)?;
+            }
+            (TaskOperationType::Disable, Some(mut task)) => {
+                task.status = TaskStatus::Disable;
+                self.task_db.insert_task(task)?;
+            }
+            (TaskOperationType::Enable, Some(mut task)) => {
+                task.status = TaskStatus::Enable;
+                self.task_db.insert_task(task)?;
+            }
+            (operation, _) => {
+                return Err(format!(
+                    "invalid task operation {:?} on task {}",
+                    operation, task_operation.id
+                )
+                .into());
+            }
+        }
+        self.task_db.insert_task_operation(task_operation.clone())?;
+        Ok(())
+    }
+
+    fn apply_node_activation(
+        &mut self,
+        node_activation: &NeedSignData<NodeActivation>,
+    ) -> Result<()> {
+        verify_node_activation(node_activation)?;
+
+        let data = &node_activation.data;
+        let mut node = match self.node_db.get_node(&data.peer_id)? {
+            Some(node) => node,
+            None => NodeData {
+                peer_id: data.peer_id.clone(),
+                ..Default::default()
+            },
+        };
+        node.bind_address = serde_cbor::from_slice(&data.account)?;
+        node.active_status = match data.operation {
+            ActivationOperation::Activate => NodeActiveStatus::Actived,
+            ActivationOperation::Deactivate => NodeActiveStatus::Inactived,
+        };
+        node.node_type = data.node_type.clone();
+        if node.node_type == NodeType::Verify {
+            node.voting_rights = node.voting_rights.max(DEFAULT_VOTING_RIGHTS);
+        }
+        self.node_db.update_voter(&node)?;
+        self.node_db.insert_node(node)?;
+        self.node_db
+            .insert_node_activation(node_activation.clone())?;
+        Ok(())
+    }
+}
+
+/// `verify_node_activation` check the activation was signed by the key of the activated peer.
+pub fn verify_node_activation(node_activation: &NeedSignData<NodeActivation>) -> Result<()> {
+    let public_key = match PublicKey::from_protobuf_encoding(&node_activation.data.pub_key) {
+        Ok(k) => k,
+        Err(_) => return Err("invalid node activation public key".to_string().into()),
+    };
+    if public_key.to_peer_id().to_base58() != node_activation.data.peer_id {
+        return Err("node activation peer id mismatch".to_string().into());
+    }
+
+    let data_bytes = node_activation.data.encode();
+    if !public_key.verify(&data_bytes, &node_activation.signature) {
+        return Err("invalid node activation signature".to_string().into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::{self, data_types::TaskType};
+
+    fn test_body() -> Body {
+        let mut body = Body::new();
+        body.tasks.push(TaskOperation {
+            id: 1,
+            operation: TaskOperationType::Add,
+            binary_hash: H256::repeat_byte(1),
+            task_type: TaskType::LongTerm,
+            node_limit: 100,
+            reward_weight: 100,
+        });
+        body.reward.push(Reward {
+            account: b"account".to_vec(),
+            amount: 10,
+        });
+        body
+    }
+
+    #[test]
+    fn test_state_transition_deterministic() {
+        let mut state_one = StateTransition::new(
+            database::open_memory_database(),
+            &StateRoots::default(),
+            &ChainParams::default(),
+        )
+        .unwrap();
+        state_one.apply_body(0, &test_body()).unwrap();
+        let mut state_two = StateTransition::new(
+            database::open_memory_database(),
+            &StateRoots::default(),
+            &ChainParams::default(),
+        )
+        .unwrap();
+        state_two.apply_body(0, &test_body()).unwrap();
+
+        assert_eq!(state_one.roots(), state_two.roots());
+        assert_eq!(state_one.account_db.get_balance(b"account").unwrap(), 10);
+        assert_eq!(state_one.account_db.get_reward(0, b"account").unwrap(), 10);
+        assert_eq!(
+            state_one.task_db.get_task_operations(1).unwrap(),
+            test_body().tasks
+        );
+    }
+
+    #[test]
+    fn test_invalid_task_operation() {
+        let mut state = StateTransition::new(
+            database::open_memory_database(),
+            &StateRoots::default(),
+            &ChainParams::default(),
+        )
+        .unwrap();
+        state.apply_body(0, &test_body()).unwrap();
+
+        // add the same task twice
+        assert!(state.apply_body(0, &test_body()).is_err());
+    }
+
+    #[test]
+    fn test_online_blocks() {
+        let mut state = StateTransition::new(
+            database::open_memory_database(),
+            &StateRoots::default(),
+            &ChainParams::default(),
+        )
+        .unwrap();
+        state.apply_body(0, &test_body()).unwrap();
+        for (peer_id, active_status) in [
+            ("worker", NodeActiveStatus::Actived),
+            ("inactived", NodeActiveStatus::Inactived),
+        ] {
+            state
+                .node_db
+                .insert_node(NodeData {
+                    peer_id: peer_id.to_owned(),
+                    active_status,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+
+        let mut body = Body::new();
+        for peer_id in ["worker", "worker", "inactived"] {
+            body.task_results.push(TaskResult {
+                id: 1,
+                peer_id: peer_id.to_owned(),
+                timestamp: 0,
+                result: vec![],
+            });
+        }
+        state.apply_body(1, &body).unwrap();
+        state.apply_body(2, &body).unwrap();
+        let online_blocks = |peer_id: &str| {
+            state
+                .node_db
+                .get_node(peer_id)
+                .unwrap()
+                .unwrap()
+                .online_blocks
+        };
+        assert_eq!(online_blocks("worker"), 2);
+        assert_eq!(online_blocks("inactived"), 0);
+    }
+}
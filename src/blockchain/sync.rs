@@ -0,0 +1,375 @@
+use std::cmp;
+
+use chrono::Local;
+use libp2p::{identity::PublicKey, PeerId};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    message::Message,
+    network::{
+        topics::{SubTopics, TopicMessage, Topics},
+        NetworkMessage,
+    },
+};
+
+use super::{
+    db::{Block, Header},
+    error::Result,
+    signature, BlockchainModule,
+};
+
+/// Max number of blocks required or answered in one sync message.
+pub const SYNC_BATCH_SIZE: u64 = 64;
+/// Interval between two sync ticks.
+pub const SYNC_TICK_INTERVAL_MILLIS: u64 = 5000;
+/// Max number of blocks the sync target is set above the current height, it moves up as the
+/// synced blocks are imported.
+pub const MAX_SYNC_AHEAD_BLOCKS: u64 = 16 * SYNC_BATCH_SIZE;
+/// The sync target is dropped when no block is imported for this long.
+pub const SYNC_STALL_TIMEOUT_MILLIS: u64 = 6 * SYNC_TICK_INTERVAL_MILLIS;
+
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize)]
+pub enum SyncState {
+    Syncing,
+    CaughtUp,
+}
+
+/// `SyncStatus` the heights are numbers of blocks, the current height is also the index of the
+/// next block to import.
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize)]
+pub struct SyncStatus {
+    pub state: SyncState,
+    pub current_height: u64,
+    pub target_height: u64,
+}
+
+/// `SyncTarget` the height announced by the peers. The heights come from peers, so the target
+/// is capped above the current height, and dropped when the sync stalls.
+///
+/// The peer announcing the target serves the sync blocks, the other peers ignore the requests,
+/// so a required range is answered once.
+#[derive(Debug, Default)]
+pub struct SyncTarget {
+    height: u64,
+    peer: Option<String>,
+    // the current height at the last progress, and when it was seen
+    progress_height: u64,
+    progress_at: u64,
+}
+
+impl SyncTarget {
+    pub fn status(&self, current_height: u64) -> SyncStatus {
+        let target_height = cmp::max(self.height, current_height);
+        let state = if target_height > current_height {
+            SyncState::Syncing
+        } else {
+            SyncState::CaughtUp
+        };
+        SyncStatus {
+            state,
+            current_height,
+            target_height,
+        }
+    }
+
+    /// `peer` the base58 peer id serving the sync blocks.
+    pub fn peer(&self) -> Option<&str> {
+        self.peer.as_deref()
+    }
+
+    /// `raise` raise the target height, at most `MAX_SYNC_AHEAD_BLOCKS` above the current height,
+    /// the announcing peer serves the blocks from then on.
+    pub fn raise(
+        &mut self,
+        target_height: u64,
+        peer: Option<String>,
+        current_height: u64,
+        now: u64,
+    ) {
+        let target_height = cmp::min(
+            target_height,
+            current_height.saturating_add(MAX_SYNC_AHEAD_BLOCKS),
+        );
+        if target_height > cmp::max(self.height, current_height) {
+            log::info!("sync target height {}", target_height);
+            if self.status(current_height).state == SyncState::CaughtUp {
+                self.progress_height = current_height;
+                self.progress_at = now;
+            }
+            self.height = target_height;
+            if peer.is_some() {
+                self.peer = peer;
+            }
+        }
+    }
+
+    /// `check_stall` drop the target when the current height didn't move for
+    /// `SYNC_STALL_TIMEOUT_MILLIS`, no peer serves the range.
+    pub fn check_stall(&mut self, current_height: u64, now: u64) {
+        if self.status(current_height).state != SyncState::Syncing {
+            return;
+        }
+        if current_height != self.progress_height {
+            self.progress_height = current_height;
+            self.progress_at = now;
+        } else if now.saturating_sub(self.progress_at) > SYNC_STALL_TIMEOUT_MILLIS {
+            log::info!(
+                "sync stalled at height {}, drop target {}",
+                current_height,
+                self.height
+            );
+            self.height = current_height;
+            self.peer = None;
+        }
+    }
+}
+
+/// `next_range` the next block range to require while syncing, at most `SYNC_BATCH_SIZE` blocks.
+pub fn next_range(status: &SyncStatus) -> Option<(u64, u64)> {
+    if status.state != SyncState::Syncing {
+        return None;
+    }
+    let from = status.current_height;
+    let to = cmp::min(from.saturating_add(SYNC_BATCH_SIZE), status.target_height) - 1;
+    Some((from, to))
+}
+
+/// `serve_range` the stored part of a required block range, at most `SYNC_BATCH_SIZE` blocks.
+pub fn serve_range(from: u64, to: u64, current_height: u64) -> Option<(u64, u64)> {
+    if from >= current_height || to < from {
+        return None;
+    }
+    let to = cmp::min(
+        cmp::min(to, from.saturating_add(SYNC_BATCH_SIZE - 1)),
+        current_height - 1,
+    );
+    Some((from, to))
+}
+
+/// `sync_batch` the blocks of an answered batch to import from the current height on, in index
+/// order. A batch not starting at the current height answers another range, it's ignored.
+pub fn sync_batch(mut blocks: Vec<Block>, current_height: u64) -> Vec<Block> {
+    blocks.sort_by_key(|b| b.header.index);
+    match blocks.first() {
+        Some(first) if first.header.index == current_height => {}
+        _ => return vec![],
+    }
+    let mut batch = vec![];
+    for (offset, block) in blocks.into_iter().enumerate() {
+        if block.header.index != current_height + offset as u64 {
+            break;
+        }
+        batch.push(block);
+    }
+    batch
+}
+
+impl BlockchainModule {
+    pub fn sync_status(&self) -> SyncStatus {
+        self.sync_target.status(self.current_block.header.index)
+    }
+
+    /// `update_sync_target` raise the sync target height when a peer shows a longer chain, the
+    /// head header must be signed by a keeper so a lying peer can't hold back block production.
+    pub fn update_sync_target(&mut self, head: &Header, peer: Option<PeerId>) -> Result<()> {
+        signature::verify_header(head, &self.keepers)?;
+        let now = Local::now().timestamp_millis() as u64;
+        self.sync_target.raise(
+            head.index.saturating_add(1),
+            peer.map(|p| p.to_base58()),
+            self.current_block.header.index,
+            now,
+        );
+        Ok(())
+    }
+
+    fn local_peer_id(&self) -> String {
+        PublicKey::Ed25519(self.local_key.public())
+            .to_peer_id()
+            .to_base58()
+    }
+
+    /// `sync_tick` require the next block range while syncing, otherwise ask peers for their head.
+    pub async fn sync_tick(&mut self) -> Result<()> {
+        let now = Local::now().timestamp_millis() as u64;
+        self.sync_target
+            .check_stall(self.current_block.header.index, now);
+        match self.sync_status().state {
+            SyncState::Syncing => self.require_next_blocks().await,
+            SyncState::CaughtUp => {
+                self.send_sync_message(
+                    SubTopics::ReqSyncHead(Local::now().timestamp_millis()),
+                    vec![],
+                )
+                .await
+            }
+        }
+    }
+
+    async fn require_next_blocks(&mut self) -> Result<()> {
+        let (from, to) = match next_range(&self.sync_status()) {
+            Some(range) => range,
+            None => return Ok(()),
+        };
+        let peer = match self.sync_target.peer() {
+            Some(peer) => peer.to_owned(),
+            None => return Ok(()),
+        };
+        log::info!("require sync blocks {} - {} from {}", from, to, peer);
+        self.send_sync_message(
+            SubTopics::ReqSyncBlocks(from, to, peer, Local::now().timestamp_millis()),
+            vec![],
+        )
+        .await
+    }
+
+    async fn send_sync_message(&mut self, sub_topic: SubTopics, data: Vec<u8>) -> Result<()> {
+        let topic_msg = TopicMessage { sub_topic, data };
+        self.network_caller
+            .notify(Message::NetworkMessage(NetworkMessage {
+                peer_id: None,
+                topic: Topics::DataSync,
+                message: serde_cbor::to_vec(&topic_msg)?,
+            }))
+            .await?;
+        Ok(())
+    }
+
+    /// `deal_sync_message` answer the sync requirements of peers and import the synced blocks,
+    /// the source is the peer which sent the message.
+    pub async fn deal_sync_message(
+        &mut self,
+        source: Option<PeerId>,
+        msg: &TopicMessage,
+    ) -> Result<()> {
+        match &msg.sub_topic {
+            SubTopics::ReqSyncHead(_) => {
+                if let Some(head) = self.db.get_latest_block()? {
+                    self.send_sync_message(
+                        SubTopics::AckSyncHead(head.header.index),
+                        serde_cbor::to_vec(&head.header)?,
+                    )
+                    .await?;
+                }
+            }
+            SubTopics::AckSyncHead(head_index) => {
+                let head: Header = serde_cbor::from_slice(&msg.data)?;
+                if head.index != *head_index {
+                    return Err("sync head index mismatch".to_string().into());
+                }
+                self.update_sync_target(&head, source)?;
+                self.require_next_blocks().await?;
+            }
+            SubTopics::ReqSyncBlocks(from, to, peer, _) => {
+                // the range is required from one peer only
+                if *peer != self.local_peer_id() {
+                    return Ok(());
+                }
+                let (from, to) = match serve_range(*from, *to, self.current_block.header.index) {
+                    Some(range) => range,
+                    None => return Ok(()),
+                };
+                let mut blocks = vec![];
+                for index in from..=to {
+                    blocks.push(self.db.get_block_by_index(index)?);
+                }
+                self.send_sync_message(SubTopics::AckSyncBlocks, serde_cbor::to_vec(&blocks)?)
+                    .await?;
+            }
+            SubTopics::AckSyncBlocks => {
+                if self.sync_status().state != SyncState::Syncing {
+                    return Ok(());
+                }
+                let blocks: Vec<Block> = serde_cbor::from_slice(&msg.data)?;
+                let batch = sync_batch(blocks, self.current_block.header.index);
+                if batch.is_empty() {
+                    return Ok(());
+                }
+                // a failed block stops the batch, the next range starts from it again
+                for block in batch {
+                    if let Err(e) = self.import_block(block.clone()).await {
+                        log::error!("import sync block {} fail: {:?}", block.header.index, e);
+                        break;
+                    }
+                    if let Err(e) = self.vote_block(&block).await {
+                        log::error!("vote sync block {} fail: {:?}", block.header.index, e);
+                    }
+                }
+                self.require_next_blocks().await?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sync_target() {
+        let mut target = SyncTarget::default();
+        assert_eq!(target.status(10).state, SyncState::CaughtUp);
+
+        // a far target is capped above the current height
+        target.raise(u64::MAX, Some("peer".to_owned()), 10, 0);
+        assert_eq!(target.peer(), Some("peer"));
+        target.raise(u64::MAX, Some("other".to_owned()), 10, 0);
+        assert_eq!(target.peer(), Some("peer"));
+        let status = target.status(10);
+        assert_eq!(status.state, SyncState::Syncing);
+        assert_eq!(status.target_height, 10 + MAX_SYNC_AHEAD_BLOCKS);
+        assert_eq!(next_range(&status), Some((10, 10 + SYNC_BATCH_SIZE - 1)));
+
+        // the imported blocks keep the target, the last batch stops at the target
+        target.check_stall(20, SYNC_STALL_TIMEOUT_MILLIS);
+        let current_height = 10 + MAX_SYNC_AHEAD_BLOCKS - 2;
+        target.check_stall(current_height, 2 * SYNC_STALL_TIMEOUT_MILLIS);
+        let status = target.status(current_height);
+        assert_eq!(status.state, SyncState::Syncing);
+        assert_eq!(
+            next_range(&status),
+            Some((current_height, current_height + 1))
+        );
+        assert_eq!(
+            target.status(10 + MAX_SYNC_AHEAD_BLOCKS).state,
+            SyncState::CaughtUp
+        );
+        assert_eq!(next_range(&target.status(10 + MAX_SYNC_AHEAD_BLOCKS)), None);
+
+        // no block for the stall timeout drops the target
+        target.check_stall(current_height, 3 * SYNC_STALL_TIMEOUT_MILLIS + 1);
+        assert_eq!(target.status(current_height).state, SyncState::CaughtUp);
+        assert_eq!(target.peer(), None);
+    }
+
+    #[test]
+    fn test_sync_batch() {
+        let block_at = |index: u64| {
+            let mut block = Block::default();
+            block.header.index = index;
+            block
+        };
+        let indexes =
+            |batch: Vec<Block>| -> Vec<u64> { batch.into_iter().map(|b| b.header.index).collect() };
+        let blocks = vec![block_at(7), block_at(5), block_at(6), block_at(9)];
+        assert_eq!(indexes(sync_batch(blocks.clone(), 5)), vec![5, 6, 7]);
+        // the batch of another range is ignored
+        assert!(sync_batch(blocks.clone(), 6).is_empty());
+        assert!(sync_batch(blocks, 4).is_empty());
+        assert!(sync_batch(vec![], 5).is_empty());
+    }
+
+    #[test]
+    fn test_serve_range() {
+        assert_eq!(serve_range(0, 9, 5), Some((0, 4)));
+        assert_eq!(
+            serve_range(0, u64::MAX, 1000),
+            Some((0, SYNC_BATCH_SIZE - 1))
+        );
+        assert_eq!(serve_range(5, 9, 5), None);
+        assert_eq!(serve_range(u64::MAX, u64::MAX, 5), None);
+        assert_eq!(serve_range(3, 2, 5), None);
+    }
+}
@@ -0,0 +1,107 @@
+use std::sync::Arc;
+
+use ethereum_types::H256;
+use kvdb::KeyValueDB;
+
+use crate::database::{data_types::TaskType, overlay::OverlayDB};
+
+use super::{
+    db::{Block, BlockchainDB, Body, HeadUpdate, TaskOperation, TaskOperationType},
+    genesis::ChainParams,
+    state::{StateRoots, StateTransition},
+};
+
+/// `TestChain` commit canonical blocks with their state for the tests, every block is the child
+/// of the previous one. Side blocks are committed without moving the head.
+pub struct TestChain {
+    pub db: Arc<dyn KeyValueDB>,
+    pub blockchain_db: BlockchainDB,
+    pub blocks: Vec<Block>,
+}
+
+impl TestChain {
+    pub fn new(db: Arc<dyn KeyValueDB>) -> TestChain {
+        TestChain {
+            blockchain_db: BlockchainDB::new(db.clone()).unwrap(),
+            db,
+            blocks: vec![],
+        }
+    }
+
+    /// `build` a chain of `count` blocks, block `i` adds the task `i`.
+    pub fn build(db: Arc<dyn KeyValueDB>, count: u64) -> TestChain {
+        let mut chain = TestChain::new(db);
+        for index in 0..count {
+            chain.push_block(add_task_body(index), &[]);
+        }
+        chain
+    }
+
+    /// `push_block` apply the body and credit the balances on the head state, then commit the
+    /// block as the new head.
+    pub fn push_block(&mut self, body: Body, balances: &[(&[u8], u64)]) -> Block {
+        let parent = self.blocks.last().cloned();
+        let block = self.commit_child(parent.as_ref(), body, balances, true);
+        self.blocks.push(block.clone());
+        block
+    }
+
+    /// `push_side_block` apply the body on the state of the parent, then commit the block
+    /// without linking it.
+    pub fn push_side_block(&mut self, parent: &Block, body: Body) -> Block {
+        self.commit_child(Some(parent), body, &[], false)
+    }
+
+    fn commit_child(
+        &mut self,
+        parent: Option<&Block>,
+        body: Body,
+        balances: &[(&[u8], u64)],
+        canonical: bool,
+    ) -> Block {
+        let mut block = Block::default();
+        block.body = body;
+        let parent_roots = match parent {
+            Some(parent) => {
+                block.header.index = parent.header.index + 1;
+                block.header.previous_hash = parent.hash().unwrap();
+                StateRoots::from_header(&parent.header)
+            }
+            None => StateRoots::default(),
+        };
+
+        let overlay = OverlayDB::new(self.db.clone());
+        let mut state =
+            StateTransition::new(overlay.clone(), &parent_roots, &ChainParams::default()).unwrap();
+        state.apply_body(block.header.index, &block.body).unwrap();
+        for (account, amount) in balances {
+            state.add_balance(account, *amount).unwrap();
+        }
+        state.commit().unwrap();
+        state.roots().write_to_header(&mut block.header);
+
+        let head_update = HeadUpdate {
+            branch: vec![block.clone()],
+            old_head_index: None,
+        };
+        let head_update = if canonical { Some(&head_update) } else { None };
+        self.blockchain_db
+            .commit_block(&block, Some(&overlay), head_update)
+            .unwrap();
+        block
+    }
+}
+
+/// `add_task_body` a body adding one long term task.
+pub fn add_task_body(id: u64) -> Body {
+    let mut body = Body::new();
+    body.tasks.push(TaskOperation {
+        id,
+        operation: TaskOperationType::Add,
+        binary_hash: H256::repeat_byte(1),
+        task_type: TaskType::LongTerm,
+        node_limit: 100,
+        reward_weight: 100,
+    });
+    body
+}
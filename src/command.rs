@@ -0,0 +1,72 @@
+use clap::{Parser, Subcommand};
+
+use crate::{
+    blockchain::{self, genesis::GenesisSpec},
+    config::Config,
+    database, dir,
+    error::WError,
+};
+
+#[derive(Parser, Debug)]
+#[clap(version, about)]
+pub struct Cli {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// `Command` the offline commands, they run on the database of a stopped node.
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Delete the state older than the history depth of the config.
+    Prune,
+    /// Check the canonical chain, its blocks, state tries and index keys.
+    Fsck {
+        /// Rewrite the wrong index keys from the checked chain.
+        #[clap(long)]
+        repair: bool,
+    },
+    /// Write the state of a canonical block to a snapshot file.
+    ExportSnapshot {
+        /// The block index, the head block by default.
+        #[clap(long)]
+        height: Option<u64>,
+        path: String,
+    },
+    /// Verify a snapshot file and restore it into an empty data directory.
+    ImportSnapshot { path: String },
+}
+
+/// `run_command` run the offline command on the database of the config.
+pub fn run_command(command: &Command, conf: &Config) -> Result<(), WError> {
+    let d = dir::Directories::new(conf.base.data_path.clone());
+    let db_backend = database::open_backend(conf.base.db_backend, d.db.as_str())?;
+    match command {
+        Command::Prune => {
+            let deleted = blockchain::prune::prune_state(db_backend, conf.base.history_depth)?;
+            println!("prune finished, {} trie nodes deleted", deleted);
+        }
+        Command::Fsck { repair } => {
+            let report =
+                blockchain::fsck::check_chain(db_backend, conf.base.prune_depth(), *repair)?;
+            for error in &report.errors {
+                println!("{}", error);
+            }
+            println!(
+                "fsck finished, {} blocks checked, {} problems found, {} index keys repaired",
+                report.checked_blocks,
+                report.errors.len(),
+                report.repaired_indexes
+            );
+        }
+        Command::ExportSnapshot { height, path } => {
+            let index = blockchain::snapshot::export_snapshot(db_backend, *height, path)?;
+            println!("snapshot of block {} exported to {}", index, path);
+        }
+        Command::ImportSnapshot { path } => {
+            let genesis = GenesisSpec::load(&conf.blockchain_config.genesis_path)?;
+            let index = blockchain::snapshot::import_snapshot(db_backend, path, &genesis)?;
+            println!("snapshot of block {} imported from {}", index, path);
+        }
+    }
+    Ok(())
+}
@@ -7,6 +7,8 @@ use std::fs::File;
 use std::io::prelude::*;
 
 use crate::api::config::ApiConfig;
+use crate::blockchain::config::BlockchainConfig;
+use crate::database::DatabaseBackend;
 use crate::network::config::NetworkConfig;
 use crate::node::config::NodeConfig;
 
@@ -16,6 +18,7 @@ pub struct Config {
     pub network: NetworkConfig,
     pub node_config: NodeConfig,
     pub api_config: ApiConfig,
+    pub blockchain_config: BlockchainConfig,
 }
 
 pub fn load_config(file_path: String) -> Result<Config, ConfigError> {
@@ -57,4 +60,54 @@ impl From<toml::de::Error> for ConfigError {
 #[derive(Deserialize, Debug)]
 pub struct BaseConfig {
     pub data_path: String,
+    #[serde(default)]
+    pub db_backend: DatabaseBackend,
+    #[serde(default)]
+    pub state_mode: StateMode,
+    #[serde(default = "default_history_depth")]
+    pub history_depth: u64,
+    #[serde(default = "default_trie_cache_size")]
+    pub trie_cache_size: usize,
+}
+
+impl BaseConfig {
+    /// `trie_cache_bytes` the capacity of the trie node cache, `trie_cache_size` is in MiB.
+    pub fn trie_cache_bytes(&self) -> usize {
+        self.trie_cache_size * 1024 * 1024
+    }
+
+    /// `prune_depth` the number of block states kept by a pruned node, none on an archive node.
+    pub fn prune_depth(&self) -> Option<u64> {
+        match self.state_mode {
+            StateMode::Archive => None,
+            StateMode::Pruned => Some(self.history_depth),
+        }
+    }
+}
+
+/// `StateMode` an archive node keeps the state of every block, a pruned node only keeps the
+/// states of the last `history_depth` blocks.
+///
+/// Only a pruned node journals the trie nodes of every block. An archive node switched to pruned
+/// prunes the blocks stored from then on, run the offline `prune` command once to drop the stale
+/// nodes written before.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StateMode {
+    Archive,
+    Pruned,
+}
+
+impl Default for StateMode {
+    fn default() -> Self {
+        StateMode::Archive
+    }
+}
+
+fn default_history_depth() -> u64 {
+    256
+}
+
+fn default_trie_cache_size() -> usize {
+    64
 }
@@ -0,0 +1,244 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use kvdb::{DBOp, DBTransaction, DBValue, KeyValueDB};
+use lru::LruCache;
+use parity_util_mem::{MallocSizeOf, MallocSizeOfOps};
+use serde::{Deserialize, Serialize};
+
+use super::{db, KEY_ROOT};
+
+/// `TRIE_COLUMNS` the columns holding trie nodes, the only ones cached.
+pub const TRIE_COLUMNS: [u32; 8] = [
+    db::COL_ACCOUNT,
+    db::COL_BLOCK_HEADERS,
+    db::COL_BLOCK_BODIES,
+    db::COL_NODE_LIST,
+    db::COL_NODE_LIST_ACTIVATED,
+    db::COL_TASK_LIST,
+    db::COL_TASK_RESULT,
+    db::COL_TASK_OPERATIONS,
+];
+
+/// `is_trie_node` whether the key is a trie node, the trie columns also hold the stored root.
+fn is_trie_node(col: u32, key: &[u8]) -> bool {
+    TRIE_COLUMNS.contains(&col) && key != KEY_ROOT
+}
+
+/// `CacheMetrics` the counters of the trie node cache.
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize)]
+pub struct CacheMetrics {
+    pub hits: u64,
+    pub misses: u64,
+    pub size: usize,
+    pub capacity: usize,
+}
+
+struct NodeLru {
+    nodes: LruCache<(u32, Vec<u8>), DBValue>,
+    size: usize,
+    // bumped by every write, a read started before a write doesn't fill the cache
+    generation: u64,
+}
+
+impl NodeLru {
+    fn remove(&mut self, col: u32, key: &[u8]) {
+        if let Some(value) = self.nodes.pop(&(col, key.to_vec())) {
+            self.size -= key.len() + value.len();
+        }
+    }
+}
+
+/// `CachedDB` keep the most recently read trie nodes of a backend in memory, at most
+/// `capacity` bytes of keys and values.
+///
+/// Trie nodes are stored under their hash, so every `AppDB` opened on the same `CachedDB` shares
+/// the nodes it reads. Only the trie nodes are cached, the chain metadata of `COL_EXTRA` and the
+/// stored roots are always read from the backend. Writes drop the written keys from the cache.
+pub struct CachedDB {
+    backend: Arc<dyn KeyValueDB>,
+    capacity: usize,
+    cache: Mutex<NodeLru>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl CachedDB {
+    pub fn new(backend: Arc<dyn KeyValueDB>, capacity: usize) -> Arc<CachedDB> {
+        Arc::new(CachedDB {
+            backend,
+            capacity,
+            cache: Mutex::new(NodeLru {
+                nodes: LruCache::unbounded(),
+                size: 0,
+                generation: 0,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+
+    pub fn metrics(&self) -> CacheMetrics {
+        CacheMetrics {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            size: self.cache.lock().unwrap().size,
+            capacity: self.capacity,
+        }
+    }
+
+    fn insert(&self, col: u32, key: &[u8], value: &DBValue, generation: u64) {
+        let entry_size = key.len() + value.len();
+        if entry_size > self.capacity {
+            return;
+        }
+        let mut cache = self.cache.lock().unwrap();
+        if cache.generation != generation {
+            return;
+        }
+        cache.remove(col, key);
+        while cache.size + entry_size > self.capacity {
+            match cache.nodes.pop_lru() {
+                Some(((_, k), v)) => cache.size -= k.len() + v.len(),
+                None => break,
+            }
+        }
+        cache.nodes.put((col, key.to_vec()), value.clone());
+        cache.size += entry_size;
+    }
+}
+
+impl MallocSizeOf for CachedDB {
+    fn size_of(&self, ops: &mut MallocSizeOfOps) -> usize {
+        self.backend.size_of(ops) + self.cache.lock().unwrap().size
+    }
+}
+
+impl KeyValueDB for CachedDB {
+    fn get(&self, col: u32, key: &[u8]) -> io::Result<Option<DBValue>> {
+        if !is_trie_node(col, key) {
+            return self.backend.get(col, key);
+        }
+        let generation = {
+            let mut cache = self.cache.lock().unwrap();
+            if let Some(value) = cache.nodes.get(&(col, key.to_vec())) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(Some(value.clone()));
+            }
+            cache.generation
+        };
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        let value = self.backend.get(col, key)?;
+        if let Some(value) = &value {
+            self.insert(col, key, value, generation);
+        }
+        Ok(value)
+    }
+
+    fn get_by_prefix(&self, col: u32, prefix: &[u8]) -> Option<Box<[u8]>> {
+        self.backend.get_by_prefix(col, prefix)
+    }
+
+    fn write(&self, transaction: DBTransaction) -> io::Result<()> {
+        let mut cache = self.cache.lock().unwrap();
+        for op in &transaction.ops {
+            match op {
+                DBOp::Insert { col, key, .. } | DBOp::Delete { col, key } => {
+                    cache.remove(*col, key);
+                }
+                DBOp::DeletePrefix { col, prefix } => {
+                    let keys: Vec<Vec<u8>> = cache
+                        .nodes
+                        .iter()
+                        .filter(|((c, k), _)| c == col && k.starts_with(prefix))
+                        .map(|((_, k), _)| k.clone())
+                        .collect();
+                    for key in keys {
+                        cache.remove(*col, &key);
+                    }
+                }
+            }
+        }
+        cache.generation += 1;
+        self.backend.write(transaction)
+    }
+
+    fn iter<'a>(&'a self, col: u32) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+        self.backend.iter(col)
+    }
+
+    fn iter_with_prefix<'a>(
+        &'a self,
+        col: u32,
+        prefix: &'a [u8],
+    ) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+        self.backend.iter_with_prefix(col, prefix)
+    }
+
+    fn restore(&self, new_db: &str) -> io::Result<()> {
+        let mut cache = self.cache.lock().unwrap();
+        cache.nodes.clear();
+        cache.size = 0;
+        cache.generation += 1;
+        self.backend.restore(new_db)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::{self, AppDB};
+
+    #[test]
+    fn test_cached_db() {
+        let cached_db = CachedDB::new(database::open_memory_database(), 1 << 20);
+        let mut app_db = AppDB::new(cached_db.clone(), 1, [0u8; 32]).unwrap();
+        app_db.insert(b"hello", b"world").unwrap();
+        app_db.commit().unwrap();
+
+        let reader = app_db.clone();
+        assert_eq!(reader.get(b"hello").unwrap(), Some(b"world".to_vec()));
+        let misses = cached_db.metrics().misses;
+        assert_eq!(reader.get(b"hello").unwrap(), Some(b"world".to_vec()));
+        let metrics = cached_db.metrics();
+        assert_eq!(metrics.misses, misses);
+        assert!(metrics.hits > 0);
+
+        // a written key is read again from the backend
+        let mut tx = cached_db.transaction();
+        tx.put(1, b"key", b"one");
+        cached_db.write(tx).unwrap();
+        assert_eq!(cached_db.get(1, b"key").unwrap(), Some(b"one".to_vec()));
+        let mut tx = cached_db.transaction();
+        tx.put(1, b"key", b"two");
+        cached_db.write(tx).unwrap();
+        assert_eq!(cached_db.get(1, b"key").unwrap(), Some(b"two".to_vec()));
+
+        // the cache never grows over its capacity
+        let small_db = CachedDB::new(database::open_memory_database(), 8);
+        let mut tx = small_db.transaction();
+        tx.put(1, b"a", b"1234");
+        tx.put(1, b"b", b"5678");
+        small_db.write(tx).unwrap();
+        small_db.get(1, b"a").unwrap();
+        small_db.get(1, b"b").unwrap();
+        assert_eq!(small_db.metrics().size, 5);
+
+        // the chain metadata and the stored roots are never cached
+        let mut tx = small_db.transaction();
+        tx.put(db::COL_EXTRA, b"c", b"1");
+        tx.put(1, KEY_ROOT, b"2");
+        small_db.write(tx).unwrap();
+        let metrics = small_db.metrics();
+        small_db.get(db::COL_EXTRA, b"c").unwrap();
+        small_db.get(1, KEY_ROOT).unwrap();
+        assert_eq!(small_db.metrics(), metrics);
+    }
+}
@@ -1,9 +1,10 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
+use parity_scale_codec::{Decode, Encode};
 use serde::Deserialize;
 use serde::Serialize;
 
-#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize, Encode, Decode)]
 pub enum NodeType {
     Work,
     Verify,
@@ -15,7 +16,7 @@ impl Default for NodeType {
     }
 }
 
-#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize, Encode, Decode)]
 pub enum NodeStatus {
     Online,
     Offline,
@@ -27,7 +28,7 @@ impl Default for NodeStatus {
     }
 }
 
-#[derive(Debug, PartialEq, Clone, Eq, Hash, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Clone, Eq, Hash, Deserialize, Serialize, Encode, Decode)]
 pub enum NodeActiveStatus {
     Inactived,
     Actived,
@@ -39,7 +40,7 @@ impl Default for NodeActiveStatus {
     }
 }
 
-#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize, Default)]
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize, Encode, Decode, Default)]
 pub struct NodeData {
     pub peer_id: String,
     pub bind_address: String,
@@ -47,14 +48,14 @@ pub struct NodeData {
     pub active_status: NodeActiveStatus,
     pub node_type: NodeType,
     pub stake_amount: u128,
-    pub worker_stake_amount: HashMap<String, u128>,
+    pub worker_stake_amount: BTreeMap<String, u128>,
     pub vote_amount: u128,
     pub voting_rights: u128,
-    pub worker_vote_amount: HashMap<String, u128>,
+    pub worker_vote_amount: BTreeMap<String, u128>,
     pub online_blocks: u128,
 }
 
-#[derive(Debug, PartialEq, Clone, Eq, Hash, Deserialize, Serialize, Default)]
+#[derive(Debug, PartialEq, Clone, Eq, Hash, Deserialize, Serialize, Encode, Decode, Default)]
 pub struct TaskDistributeData {
     pub task_id: u64,
     pub peer_id: String,
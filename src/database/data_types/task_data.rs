@@ -1,7 +1,8 @@
 use ethereum_types::H256;
+use parity_scale_codec::{Decode, Encode};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq, Clone, Eq, Hash, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Clone, Eq, Hash, Deserialize, Serialize, Encode, Decode)]
 pub struct TaskData {
     pub id: u64,
     pub hash: H256,
@@ -12,13 +13,13 @@ pub struct TaskData {
     pub reward_weight: u64,
 }
 
-#[derive(Debug, PartialEq, Clone, Eq, Hash, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Clone, Eq, Hash, Deserialize, Serialize, Encode, Decode)]
 pub enum TaskStatus {
     Enable,
     Disable,
 }
 
-#[derive(Debug, PartialEq, Clone, Eq, Hash, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Clone, Eq, Hash, Deserialize, Serialize, Encode, Decode)]
 pub enum TaskType {
     LongTerm,
     Single,
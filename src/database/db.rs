@@ -1,13 +1,13 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use hash_db::{AsHashDB, HashDB, HashDBRef, Hasher, Prefix};
 use keccak_hasher::KeccakHasher;
-use kvdb::KeyValueDB;
+use kvdb::{DBTransaction, KeyValueDB};
 use kvdb_rocksdb::{Database, DatabaseConfig};
 use trie_db::DBValue;
 use trie_db::{NodeCodec, TrieLayout};
 
-use super::{error::Result, trie_layout::ExtensionLayout};
+use super::{error::Result, journal, trie_layout::ExtensionLayout};
 
 // Database column indexes.
 pub const COL_EXTRA: u32 = 0;
@@ -22,12 +22,19 @@ pub const COL_TASK_OPERATIONS: u32 = 8;
 
 pub const NUM_COLUMNS: u32 = 9;
 
+/// `DB` the trie node store of a column, new trie nodes are kept in memory until `commit`.
+///
+/// The nodes inserted and removed since the last commit are counted, on a pruned node the state
+/// columns record them in the pending journal of the state when they're committed.
 #[derive(Clone)]
 pub struct DB {
     pub data: Arc<dyn KeyValueDB>,
     column: u32,
     pub hashed_null_node: [u8; 32],
     null_node_data: [u8; 1],
+    changes: HashMap<Vec<u8>, DBValue>,
+    inserted: HashMap<Vec<u8>, u32>,
+    removed: HashMap<Vec<u8>, u32>,
 }
 
 impl DB {
@@ -37,6 +44,9 @@ impl DB {
             column,
             hashed_null_node: <ExtensionLayout as TrieLayout>::Codec::hashed_null_node(),
             null_node_data: [0u8],
+            changes: HashMap::new(),
+            inserted: HashMap::new(),
+            removed: HashMap::new(),
         };
 
         Ok(db)
@@ -57,6 +67,38 @@ impl DB {
         tx.delete(self.column, &key);
         Ok(self.data.write(tx)?)
     }
+
+    /// `drain_into` move the trie nodes not committed yet into the transaction, on a pruned node
+    /// the nodes inserted and removed in a state column are added to the pending journal.
+    pub fn drain_into(&mut self, tx: &mut DBTransaction) -> Result<()> {
+        if journal::JOURNAL_COLUMNS.contains(&self.column)
+            && journal::is_enabled(self.data.as_ref())?
+        {
+            journal::record_pending(
+                self.data.as_ref(),
+                tx,
+                self.column,
+                &self.inserted,
+                &self.removed,
+            )?;
+        }
+        self.inserted.clear();
+        self.removed.clear();
+        for (key, value) in self.changes.drain() {
+            tx.put_vec(self.column, &key, value);
+        }
+        Ok(())
+    }
+
+    /// `commit` write the trie nodes not committed yet in one transaction.
+    pub fn commit(&mut self) -> Result<()> {
+        if self.changes.is_empty() && self.removed.is_empty() {
+            return Ok(());
+        }
+        let mut tx = self.data.transaction();
+        self.drain_into(&mut tx)?;
+        Ok(self.data.write(tx)?)
+    }
 }
 
 impl HashDB<KeccakHasher, DBValue> for DB {
@@ -66,6 +108,9 @@ impl HashDB<KeccakHasher, DBValue> for DB {
         }
 
         let key = prefixed_key(key, prefix);
+        if let Some(value) = self.changes.get(&key) {
+            return Some(value.clone());
+        }
         match self.data.get(self.column, &key) {
             Ok(Some(value)) => Some(value.clone()),
             _ => None,
@@ -78,6 +123,9 @@ impl HashDB<KeccakHasher, DBValue> for DB {
         }
 
         let key = prefixed_key(key, prefix);
+        if self.changes.contains_key(&key) {
+            return true;
+        }
         match self.data.has_key(self.column, &key) {
             Ok(r) => r,
             _ => false,
@@ -90,12 +138,8 @@ impl HashDB<KeccakHasher, DBValue> for DB {
         }
 
         let key = prefixed_key(&key, prefix);
-        let mut tx = self.data.transaction();
-        tx.put(self.column, &key, &value);
-        match self.data.write(tx) {
-            Ok(_) => {}
-            Err(e) => {}
-        }
+        *self.inserted.entry(key.clone()).or_default() += 1;
+        self.changes.insert(key, value);
     }
 
     fn insert(&mut self, prefix: Prefix, value: &[u8]) -> <KeccakHasher as Hasher>::Out {
@@ -107,17 +151,24 @@ impl HashDB<KeccakHasher, DBValue> for DB {
         key
     }
 
+    // Trie nodes are shared by the states of older blocks, a node removed from the new state
+    // is only counted, it's deleted when the journal of the block is pruned. A node inserted
+    // since the last commit was never written and is dropped right away.
     fn remove(&mut self, key: &<KeccakHasher as Hasher>::Out, prefix: Prefix) {
         if key.as_ref() == &self.hashed_null_node {
             return;
         }
 
         let key = prefixed_key(key, prefix);
-        let mut tx = self.data.transaction();
-        tx.delete(self.column, &key);
-        match self.data.write(tx) {
-            Ok(_) => {}
-            Err(e) => {}
+        match self.inserted.get_mut(&key) {
+            Some(count) => {
+                *count -= 1;
+                if *count == 0 {
+                    self.inserted.remove(&key);
+                    self.changes.remove(&key);
+                }
+            }
+            None => *self.removed.entry(key).or_default() += 1,
         }
     }
 }
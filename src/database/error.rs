@@ -32,6 +32,9 @@ macro_rules! quick_from {
     };
 }
 
+quick_from!(String);
 quick_from!(IoError);
+quick_from!(serde_cbor::Error);
+quick_from!(parity_scale_codec::Error);
 quick_from!(Box<TrieError<[u8; 32], parity_scale_codec::Error>>);
 quick_from!(Vec<u8>);
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+
+use kvdb::{DBTransaction, KeyValueDB};
+use parity_scale_codec::{Decode, Encode};
+
+use super::{db, error::Result};
+
+/// The number of committed states referencing a trie node, by column and node key.
+pub const KEY_NODE_REFS: &[u8; 5] = b"refs:";
+/// The nodes inserted by a state not committed with its block yet.
+pub const KEY_PENDING_INSERTED: &[u8; 9] = b"inserted:";
+/// The nodes removed by a state not committed with its block yet.
+pub const KEY_PENDING_REMOVED: &[u8; 8] = b"removed:";
+/// The journal of a block, by block index and hash.
+pub const KEY_JOURNAL: &[u8; 8] = b"journal:";
+/// Set on a pruned node, the state trie nodes are only journaled when it's set.
+pub const KEY_JOURNAL_ENABLED: &[u8; 15] = b"journal_enabled";
+
+/// `JOURNAL_COLUMNS` the columns of the state tries, their nodes are journaled.
+pub const JOURNAL_COLUMNS: [u32; 6] = [
+    db::COL_ACCOUNT,
+    db::COL_NODE_LIST,
+    db::COL_NODE_LIST_ACTIVATED,
+    db::COL_TASK_LIST,
+    db::COL_TASK_RESULT,
+    db::COL_TASK_OPERATIONS,
+];
+
+/// `NodeJournal` the trie nodes a block inserted into and removed from the state tries,
+/// as column, node key and count.
+#[derive(Encode, Decode, Debug, Default, Clone, PartialEq, Eq)]
+pub struct NodeJournal {
+    pub inserted: Vec<(u32, Vec<u8>, u32)>,
+    pub removed: Vec<(u32, Vec<u8>, u32)>,
+}
+
+impl NodeJournal {
+    pub fn is_empty(&self) -> bool {
+        self.inserted.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// `is_enabled` whether the state trie nodes are journaled.
+pub fn is_enabled(db: &dyn KeyValueDB) -> Result<bool> {
+    Ok(db.get(db::COL_EXTRA, KEY_JOURNAL_ENABLED)?.is_some())
+}
+
+/// `journal_prefix` the prefix of the journals of the blocks at the index.
+pub fn journal_prefix(index: u64) -> Vec<u8> {
+    [&KEY_JOURNAL[..], &index.to_be_bytes()].concat()
+}
+
+/// `journal_key` the key of the journal of a block.
+pub fn journal_key(index: u64, block_hash: &[u8]) -> Vec<u8> {
+    [journal_prefix(index).as_slice(), block_hash].concat()
+}
+
+fn node_entry_key(prefix: &[u8], column: u32, key: &[u8]) -> Vec<u8> {
+    [prefix, &column.to_be_bytes(), key].concat()
+}
+
+fn get_count(db: &dyn KeyValueDB, key: &[u8]) -> Result<Option<u32>> {
+    match db.get(db::COL_EXTRA, key)? {
+        Some(bytes) => Ok(Some(u32::decode(&mut bytes.as_slice())?)),
+        None => Ok(None),
+    }
+}
+
+/// `record_pending` add the nodes inserted and removed by a trie commit to the pending journal
+/// of the state. The counts are read from the database, a transaction holds the nodes of one
+/// trie commit.
+pub fn record_pending(
+    db: &dyn KeyValueDB,
+    tx: &mut DBTransaction,
+    column: u32,
+    inserted: &HashMap<Vec<u8>, u32>,
+    removed: &HashMap<Vec<u8>, u32>,
+) -> Result<()> {
+    for (prefix, nodes) in [
+        (&KEY_PENDING_INSERTED[..], inserted),
+        (&KEY_PENDING_REMOVED[..], removed),
+    ] {
+        for (key, count) in nodes {
+            let entry = node_entry_key(prefix, column, key);
+            let count = get_count(db, &entry)?.unwrap_or(0) + count;
+            tx.put(db::COL_EXTRA, &entry, &count.encode());
+        }
+    }
+    Ok(())
+}
+
+/// `take_pending` the pending journal of the state, the pending entries are deleted.
+pub fn take_pending(db: &dyn KeyValueDB) -> Result<NodeJournal> {
+    let mut journal = NodeJournal::default();
+    let mut tx = db.transaction();
+    for (prefix, nodes) in [
+        (&KEY_PENDING_INSERTED[..], &mut journal.inserted),
+        (&KEY_PENDING_REMOVED[..], &mut journal.removed),
+    ] {
+        for (entry, count) in db.iter_with_prefix(db::COL_EXTRA, prefix) {
+            if entry.len() < prefix.len() + 4 {
+                return Err(format!("invalid journal entry {:?}", entry).into());
+            }
+            let mut column = [0u8; 4];
+            column.copy_from_slice(&entry[prefix.len()..prefix.len() + 4]);
+            nodes.push((
+                u32::from_be_bytes(column),
+                entry[prefix.len() + 4..].to_vec(),
+                u32::decode(&mut count.as_ref())?,
+            ));
+            tx.delete(db::COL_EXTRA, &entry);
+        }
+    }
+    db.write(tx)?;
+    Ok(journal)
+}
+
+/// `add_refs` count the nodes inserted by a committed block as referenced.
+pub fn add_refs(db: &dyn KeyValueDB, tx: &mut DBTransaction, journal: &NodeJournal) -> Result<()> {
+    for (column, key, count) in &journal.inserted {
+        let entry = node_entry_key(KEY_NODE_REFS, *column, key);
+        let count = get_count(db, &entry)?.unwrap_or(0) + count;
+        tx.put(db::COL_EXTRA, &entry, &count.encode());
+    }
+    Ok(())
+}
+
+/// `release_nodes` drop the references of the nodes, a node no state references anymore is
+/// deleted. Return the number of deleted nodes.
+pub fn release_nodes(
+    db: &dyn KeyValueDB,
+    tx: &mut DBTransaction,
+    nodes: &HashMap<(u32, Vec<u8>), u32>,
+) -> Result<usize> {
+    let mut deleted = 0;
+    for ((column, key), count) in nodes {
+        let entry = node_entry_key(KEY_NODE_REFS, *column, key);
+        // a node without references was never journaled, it's kept
+        let refs = match get_count(db, &entry)? {
+            Some(refs) => refs.saturating_sub(*count),
+            None => continue,
+        };
+        if refs == 0 {
+            tx.delete(db::COL_EXTRA, &entry);
+            tx.delete(*column, key);
+            deleted += 1;
+        } else {
+            tx.put(db::COL_EXTRA, &entry, &refs.encode());
+        }
+    }
+    Ok(deleted)
+}
@@ -0,0 +1,92 @@
+use std::sync::Arc;
+
+use kvdb::KeyValueDB;
+
+use super::{db, error::Result};
+
+pub const KEY_SCHEMA_VERSION: &[u8; 14] = b"schema_version";
+
+/// The schema version of the column layout and the record encodings written by this binary.
+/// A change to either bumps it, together with the step converting the data of the previous
+/// version in `check_schema_version`.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// `get_schema_version` the stored schema version, databases written before the version was
+/// stored are version 0.
+pub fn get_schema_version(db: &Arc<dyn KeyValueDB>) -> Result<u32> {
+    match db.get(db::COL_EXTRA, KEY_SCHEMA_VERSION)? {
+        Some(version) => Ok(serde_cbor::from_slice(&version)?),
+        None => Ok(0),
+    }
+}
+
+/// `check_schema_version` stamp an empty database with `SCHEMA_VERSION`, and refuse a database
+/// newer than the binary.
+///
+/// A database written before the version was stored holds blocks and state in encodings this
+/// binary doesn't read, it's refused as well and left untouched.
+pub fn check_schema_version(db: &Arc<dyn KeyValueDB>) -> Result<()> {
+    let version = get_schema_version(db)?;
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "database schema version {} is newer than the supported version {}",
+            version, SCHEMA_VERSION
+        )
+        .into());
+    }
+    if version == 0 {
+        if !is_empty(db) {
+            return Err(
+                "the database was written before schema versions and can't be read, \
+                 remove the data directory and sync again or import a snapshot"
+                    .to_string()
+                    .into(),
+            );
+        }
+        let mut tx = db.transaction();
+        tx.put(
+            db::COL_EXTRA,
+            KEY_SCHEMA_VERSION,
+            &serde_cbor::to_vec(&SCHEMA_VERSION)?,
+        );
+        db.write(tx)?;
+    }
+    Ok(())
+}
+
+fn is_empty(db: &Arc<dyn KeyValueDB>) -> bool {
+    (0..db::NUM_COLUMNS).all(|column| db.iter(column).next().is_none())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database;
+
+    #[test]
+    fn test_check_schema_version() {
+        let db = database::open_memory_database();
+        check_schema_version(&db).unwrap();
+        assert_eq!(get_schema_version(&db).unwrap(), SCHEMA_VERSION);
+        check_schema_version(&db).unwrap();
+
+        // an unversioned database is refused and left untouched
+        let unversioned = database::open_memory_database();
+        let mut tx = unversioned.transaction();
+        tx.put(db::COL_EXTRA, b"latest_hash", &[0u8; 32]);
+        unversioned.write(tx).unwrap();
+        let err = check_schema_version(&unversioned).unwrap_err();
+        assert!(err.message.contains("before schema versions"));
+        assert_eq!(get_schema_version(&unversioned).unwrap(), 0);
+
+        let mut tx = db.transaction();
+        tx.put(
+            db::COL_EXTRA,
+            KEY_SCHEMA_VERSION,
+            &serde_cbor::to_vec(&(SCHEMA_VERSION + 1)).unwrap(),
+        );
+        db.write(tx).unwrap();
+        assert!(check_schema_version(&db).is_err());
+    }
+}
@@ -1,41 +1,100 @@
 use ethereum_types::H256;
-use kvdb::KeyValueDB;
+use kvdb::{DBTransaction, KeyValueDB};
 use kvdb_rocksdb::{Database, DatabaseConfig};
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, default, sync::Arc};
-use trie_db::{Trie, TrieDB, TrieDBMut, TrieMut};
+use trie_db::{
+    proof, NodeCodec, Trie, TrieDB, TrieDBIterator, TrieDBMut, TrieIterator, TrieLayout, TrieMut,
+};
 
-use self::{db::DB, trie_layout::ExtensionLayout};
+use self::{
+    db::DB,
+    trie_layout::{with_layout, ExtensionLayout},
+};
 use error::Result;
 
-mod trie_layout;
+pub mod trie_layout;
 
+pub mod cache;
 pub mod data_types;
 pub mod db;
 pub mod error;
+pub mod journal;
+pub mod migration;
+pub mod overlay;
+pub mod prune;
 
 pub const KEY_ROOT: &[u8; 4] = b"root";
 
+/// `DatabaseBackend` the storage behind the databases, the memory backend is lost on exit.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseBackend {
+    RocksDB,
+    Memory,
+}
+
+impl Default for DatabaseBackend {
+    fn default() -> Self {
+        DatabaseBackend::RocksDB
+    }
+}
+
+/// `open_backend` open the database of the backend then check its schema version, the path is
+/// only used by rocksdb.
+pub fn open_backend(backend: DatabaseBackend, client_path: &str) -> Result<Arc<dyn KeyValueDB>> {
+    let db = match backend {
+        DatabaseBackend::RocksDB => open_database(client_path)?,
+        DatabaseBackend::Memory => open_memory_database(),
+    };
+    migration::check_schema_version(&db)?;
+    Ok(db)
+}
+
 pub fn open_database(client_path: &str) -> Result<Arc<dyn KeyValueDB>> {
     let db_config = DatabaseConfig::with_columns(db::NUM_COLUMNS);
 
     Ok(Arc::new(Database::open(&db_config, client_path)?))
 }
 
+/// `open_memory_database` open an empty database in memory, with all the columns.
+pub fn open_memory_database() -> Arc<dyn KeyValueDB> {
+    Arc::new(kvdb_memorydb::create(db::NUM_COLUMNS))
+}
+
 #[derive(Clone)]
 pub struct AppDB {
     db: DB,
     root: [u8; 32],
+    inline_threshold: u32,
 }
 
 impl AppDB {
     pub fn new(db_backend: Arc<dyn KeyValueDB>, column: u32, root: [u8; 32]) -> Result<AppDB> {
         let db = DB::new(db_backend, column)?;
 
-        let db = AppDB { root, db };
+        // A zero root means nothing was stored yet, open it as an empty trie.
+        let root = if root == [0u8; 32] {
+            db.hashed_null_node
+        } else {
+            root
+        };
+        let db = AppDB {
+            root,
+            db,
+            inline_threshold: trie_layout::INLINE_VALUE_THRESHOLD,
+        };
         Ok(db)
     }
 
+    /// `set_inline_threshold` set the inline value threshold of the trie writes, the state tries
+    /// use the threshold of the chain.
+    pub fn set_inline_threshold(&mut self, threshold: u32) -> Result<()> {
+        trie_layout::check_inline_threshold(threshold)?;
+        self.inline_threshold = threshold;
+        Ok(())
+    }
+
     // get data from block
     pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         let db = TrieDB::<ExtensionLayout>::new(&self.db, &self.root)?;
@@ -45,45 +104,172 @@ impl AppDB {
 
     // insert data to block
     pub fn insert(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
-        let mut db = TrieDBMut::<ExtensionLayout>::new(&mut self.db, &mut self.root);
-        db.insert(key, value)?;
-        db.commit();
-        Ok(())
+        check_key(key)?;
+        with_layout!(self.inline_threshold, Layout, {
+            let mut db = TrieDBMut::<Layout>::from_existing(&mut self.db, &mut self.root)?;
+            db.insert(key, value)?;
+            db.commit();
+            Ok(())
+        })
     }
 
     // multi insert data to block
     pub fn multi_insert(&mut self, data: Vec<(&[u8], &[u8])>) -> Result<()> {
-        let mut db = TrieDBMut::<ExtensionLayout>::new(&mut self.db, &mut self.root);
-        for (key, value) in data {
-            db.insert(key, value)?;
+        for (key, _) in &data {
+            check_key(key)?;
         }
+        with_layout!(self.inline_threshold, Layout, {
+            let mut db = TrieDBMut::<Layout>::from_existing(&mut self.db, &mut self.root)?;
+            for (key, value) in data {
+                db.insert(key, value)?;
+            }
 
-        db.commit();
-        Ok(())
+            db.commit();
+            Ok(())
+        })
     }
 
     // remove data from block
     pub fn remove(&mut self, key: &[u8]) -> Result<()> {
-        let mut db = TrieDBMut::<ExtensionLayout>::new(&mut self.db, &mut self.root);
-        db.remove(key)?;
-        db.commit();
-        Ok(())
+        with_layout!(self.inline_threshold, Layout, {
+            let mut db = TrieDBMut::<Layout>::from_existing(&mut self.db, &mut self.root)?;
+            db.remove(key)?;
+            db.commit();
+            Ok(())
+        })
     }
 
     // multi remove data from block
     pub fn multi_remove(&mut self, keys: Vec<&[u8]>) -> Result<()> {
-        let mut db = TrieDBMut::<ExtensionLayout>::new(&mut self.db, &mut self.root);
-        for key in keys {
-            db.remove(key)?;
-        }
+        with_layout!(self.inline_threshold, Layout, {
+            let mut db = TrieDBMut::<Layout>::from_existing(&mut self.db, &mut self.root)?;
+            for key in keys {
+                db.remove(key)?;
+            }
 
-        db.commit();
-        Ok(())
+            db.commit();
+            Ok(())
+        })
     }
 
     pub fn get_root(&self) -> [u8; 32] {
         self.root
     }
+
+    /// `list` the key/values starting with the prefix in key order, from the start key on,
+    /// at most `limit` items. The last key of a page followed by a zero byte starts the next page.
+    pub fn list(
+        &self,
+        prefix: &[u8],
+        start: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let db = TrieDB::<ExtensionLayout>::new(&self.db, &self.root)?;
+        let mut iter = TrieDBIterator::new(&db)?;
+        let seek_key = match start {
+            Some(start) if start > prefix => start,
+            _ => prefix,
+        };
+        iter.seek(seek_key)?;
+
+        let mut items = vec![];
+        for item in iter.take(limit) {
+            let (key, value) = item?;
+            if !key.starts_with(prefix) {
+                break;
+            }
+            items.push((key, value));
+        }
+        Ok(items)
+    }
+
+    /// `commit` write the trie changes since the last commit in one transaction,
+    /// until then they're only visible to this `AppDB`.
+    pub fn commit(&mut self) -> Result<()> {
+        self.db.commit()
+    }
+
+    /// `drain_into` move the trie changes since the last commit into the transaction,
+    /// to write them together with other data.
+    pub fn drain_into(&mut self, tx: &mut DBTransaction) -> Result<()> {
+        self.db.drain_into(tx)
+    }
+
+    /// `get_with_proof` get the values of the keys with a compact proof against the root,
+    /// a missing key is proven absent.
+    pub fn get_with_proof(&self, keys: &[Vec<u8>]) -> Result<StateProof> {
+        let db = TrieDB::<ExtensionLayout>::new(&self.db, &self.root)?;
+        let mut items = vec![];
+        for key in keys {
+            items.push((key.clone(), db.get(key)?));
+        }
+        let proof = proof::generate_proof(&db, keys)?;
+        Ok(StateProof {
+            root: H256(self.root),
+            items,
+            proof,
+        })
+    }
+}
+
+/// `check_key` a key longer than the partial key of a trie node can hold is refused.
+fn check_key(key: &[u8]) -> Result<()> {
+    if key.len() * 2 > trie_layout::NIBBLE_SIZE_BOUND {
+        return Err(format!("trie key of {} bytes is too long", key.len()).into());
+    }
+    Ok(())
+}
+
+/// `StateProof` the values of the keys and the trie nodes proving them against the root.
+#[derive(Debug, PartialEq, Clone, Eq, Deserialize, Serialize)]
+pub struct StateProof {
+    pub root: H256,
+    pub items: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    pub proof: Vec<Vec<u8>>,
+}
+
+/// `verify_proof` check the proof items against a trie root taken from a trusted header,
+/// it doesn't need any database.
+pub fn verify_proof(root: H256, state_proof: &StateProof) -> Result<()> {
+    let root = trie_root(root);
+    if root != trie_root(state_proof.root) {
+        return Err(format!("proof root mismatch, expected {:?}", H256(root)).into());
+    }
+    match proof::verify_proof::<ExtensionLayout, _, _, _>(
+        &root,
+        &state_proof.proof,
+        &state_proof.items,
+    ) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("invalid state proof: {:?}", e).into()),
+    }
+}
+
+/// `trie_root` a zero root is the root of an empty trie.
+fn trie_root(root: H256) -> [u8; 32] {
+    if root.is_zero() {
+        <ExtensionLayout as TrieLayout>::Codec::hashed_null_node()
+    } else {
+        root.to_fixed_bytes()
+    }
+}
+
+/// `calc_root` calculate the trie root of the data set with the inline value threshold of the
+/// chain, an empty data set has a zero root. The trie is never committed, so nothing is written.
+pub fn calc_root(
+    db_backend: Arc<dyn KeyValueDB>,
+    column: u32,
+    data: Vec<(&[u8], &[u8])>,
+    inline_threshold: u32,
+) -> Result<H256> {
+    if data.is_empty() {
+        return Ok(H256::zero());
+    }
+
+    let mut db = AppDB::new(db_backend, column, H256::zero().to_fixed_bytes())?;
+    db.set_inline_threshold(inline_threshold)?;
+    db.multi_insert(data)?;
+    Ok(H256(db.get_root()))
 }
 
 pub fn get_root(db: &Arc<dyn KeyValueDB>, column: u32) -> Result<H256> {
@@ -97,6 +283,8 @@ pub fn get_root(db: &Arc<dyn KeyValueDB>, column: u32) -> Result<H256> {
 mod tests {
     use super::*;
 
+    use std::collections::HashSet;
+
     #[test]
     fn test_database() {
         let dir = tempfile::Builder::new()
@@ -124,4 +312,151 @@ mod tests {
             println!("back_value {:?}", value);
         }
     }
+
+    #[test]
+    fn test_app_db_commit() {
+        let db_backend = open_memory_database();
+        let mut app_db = AppDB::new(db_backend.clone(), 0, [0u8; 32]).unwrap();
+        app_db.insert(b"hello", b"world").unwrap();
+        app_db.insert(b"help", b"me").unwrap();
+        assert_eq!(app_db.get(b"hello").unwrap(), Some(b"world".to_vec()));
+        assert_eq!(db_backend.iter(0).count(), 0);
+
+        app_db.commit().unwrap();
+        let reopened = AppDB::new(db_backend, 0, app_db.get_root()).unwrap();
+        assert_eq!(reopened.get(b"help").unwrap(), Some(b"me".to_vec()));
+    }
+
+    #[test]
+    fn test_app_db_large_value() {
+        let db_backend = open_memory_database();
+        let large_value = vec![7u8; trie_layout::INLINE_VALUE_THRESHOLD as usize * 2];
+        let mut app_db = AppDB::new(db_backend.clone(), 0, [0u8; 32]).unwrap();
+        app_db.insert(b"large", &large_value).unwrap();
+        app_db.insert(b"large_too", &large_value).unwrap();
+        app_db.insert(b"small", b"value").unwrap();
+        app_db.commit().unwrap();
+
+        // the value nodes are kept by a sweep
+        let mut keep = HashSet::new();
+        app_db.collect_node_keys(&mut keep).unwrap();
+        let mut tx = db_backend.transaction();
+        tx.put(0, b"garbage", b"node");
+        db_backend.write(tx).unwrap();
+        assert_eq!(prune::sweep_column(&db_backend, 0, &keep).unwrap(), 1);
+
+        let reopened = AppDB::new(db_backend, 0, app_db.get_root()).unwrap();
+        assert_eq!(reopened.get(b"large").unwrap(), Some(large_value.clone()));
+        assert_eq!(reopened.get(b"large_too").unwrap(), Some(large_value));
+        assert_eq!(reopened.get(b"small").unwrap(), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn test_app_db_inline_threshold() {
+        let db_backend = open_memory_database();
+        let value = vec![7u8; 100];
+        let root_of = |threshold: u32| {
+            let mut app_db = AppDB::new(db_backend.clone(), 0, [0u8; 32]).unwrap();
+            app_db.set_inline_threshold(threshold).unwrap();
+            app_db.insert(b"key", &value).unwrap();
+            app_db.commit().unwrap();
+            app_db.get_root()
+        };
+        let hashed_root = root_of(64);
+        let inline_root = root_of(128);
+        assert_ne!(hashed_root, inline_root);
+
+        // the default layout reads the tries of every threshold
+        for root in [hashed_root, inline_root] {
+            let app_db = AppDB::new(db_backend.clone(), 0, root).unwrap();
+            assert_eq!(app_db.get(b"key").unwrap(), Some(value.clone()));
+        }
+        let mut app_db = AppDB::new(db_backend, 0, [0u8; 32]).unwrap();
+        assert!(app_db.set_inline_threshold(100).is_err());
+    }
+
+    #[test]
+    fn test_app_db_long_keys() {
+        let db_backend = open_memory_database();
+        let large_value = vec![7u8; trie_layout::INLINE_VALUE_THRESHOLD as usize];
+        // the keys share a 100 bytes extension, the leaves hold up to 200 bytes partial keys
+        let shared = vec![1u8; 100];
+        let mut items = vec![
+            ([&shared[..], &[2u8]].concat(), b"short".to_vec()),
+            ([&shared[..], &[3u8]].concat(), large_value.clone()),
+            (vec![4u8; 200], b"long leaf".to_vec()),
+            (vec![5u8; 200], large_value),
+        ];
+        let mut app_db = AppDB::new(db_backend.clone(), 0, [0u8; 32]).unwrap();
+        for (key, value) in &items {
+            app_db.insert(key, value).unwrap();
+        }
+        app_db.commit().unwrap();
+
+        let reopened = AppDB::new(db_backend, 0, app_db.get_root()).unwrap();
+        for (key, value) in &items {
+            assert_eq!(reopened.get(key).unwrap(), Some(value.clone()));
+        }
+        items.sort();
+        assert_eq!(reopened.list(&[], None, 10).unwrap(), items);
+        let keys: Vec<Vec<u8>> = items.iter().map(|(key, _)| key.clone()).collect();
+        let state_proof = reopened.get_with_proof(&keys).unwrap();
+        verify_proof(state_proof.root, &state_proof).unwrap();
+
+        let mut app_db = reopened;
+        let too_long = vec![1u8; trie_layout::NIBBLE_SIZE_BOUND / 2 + 1];
+        assert!(app_db.insert(&too_long, b"value").is_err());
+    }
+
+    #[test]
+    fn test_app_db_list() {
+        let db_backend = open_memory_database();
+        let mut app_db = AppDB::new(db_backend, 0, [0u8; 32]).unwrap();
+        for key in [b"b2", b"a1", b"b1", b"b3", b"c1"] {
+            app_db.insert(key, key).unwrap();
+        }
+
+        let keys = |items: Vec<(Vec<u8>, Vec<u8>)>| -> Vec<Vec<u8>> {
+            items.into_iter().map(|(k, _)| k).collect()
+        };
+        assert_eq!(
+            keys(app_db.list(b"", None, 10).unwrap()),
+            vec![b"a1", b"b1", b"b2", b"b3", b"c1"]
+        );
+        assert_eq!(
+            keys(app_db.list(b"b", None, 2).unwrap()),
+            vec![b"b1", b"b2"]
+        );
+        assert_eq!(
+            keys(app_db.list(b"b", Some(b"b2\0"), 2).unwrap()),
+            vec![b"b3"]
+        );
+        assert!(app_db.list(b"d", None, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_state_proof() {
+        let dir = tempfile::Builder::new()
+            .prefix("worker_test")
+            .tempdir()
+            .unwrap();
+        let path = dir.path().join("db");
+        let db_backend = open_database(path.to_str().unwrap()).expect("open database failed");
+        let mut app_db = AppDB::new(db_backend, 0, [0u8; 32]).unwrap();
+        app_db.insert(b"hello", b"world").unwrap();
+        app_db.insert(b"help", b"me").unwrap();
+        let root = H256(app_db.get_root());
+
+        let state_proof = app_db
+            .get_with_proof(&[b"hello".to_vec(), b"missing".to_vec()])
+            .unwrap();
+        assert_eq!(state_proof.items[0].1, Some(b"world".to_vec()));
+        assert_eq!(state_proof.items[1].1, None);
+        assert!(verify_proof(root, &state_proof).is_ok());
+
+        let mut forged_proof = state_proof.clone();
+        forged_proof.items[0].1 = Some(b"forged".to_vec());
+        assert!(verify_proof(root, &forged_proof).is_err());
+        assert!(verify_proof(H256::repeat_byte(1), &state_proof).is_err());
+    }
 }
@@ -0,0 +1,161 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::{Arc, RwLock},
+};
+
+use kvdb::{DBOp, DBTransaction, DBValue, KeyValueDB};
+use parity_util_mem::{MallocSizeOf, MallocSizeOfOps};
+
+type Changes = BTreeMap<(u32, Vec<u8>), Option<DBValue>>;
+
+/// `OverlayDB` buffer the writes over a backend, reads see the buffered writes first.
+///
+/// Nothing reaches the backend until `drain_into` moves the buffered writes into a transaction,
+/// so the tries of a block state are written together with the block itself.
+pub struct OverlayDB {
+    backend: Arc<dyn KeyValueDB>,
+    changes: RwLock<Changes>,
+}
+
+impl OverlayDB {
+    pub fn new(backend: Arc<dyn KeyValueDB>) -> Arc<OverlayDB> {
+        Arc::new(OverlayDB {
+            backend,
+            changes: RwLock::new(BTreeMap::new()),
+        })
+    }
+
+    /// `drain_into` move the buffered writes into the transaction of the backend.
+    pub fn drain_into(&self, tx: &mut DBTransaction) {
+        let changes = std::mem::take(&mut *self.changes.write().unwrap());
+        for ((col, key), value) in changes {
+            match value {
+                Some(value) => tx.put_vec(col, &key, value),
+                None => tx.delete(col, &key),
+            }
+        }
+    }
+
+    /// `merged` the key/values of the column starting with the prefix, buffered writes win.
+    fn merged(&self, col: u32, prefix: &[u8]) -> BTreeMap<Vec<u8>, DBValue> {
+        let mut merged: BTreeMap<Vec<u8>, DBValue> = self
+            .backend
+            .iter_with_prefix(col, prefix)
+            .map(|(k, v)| (k.into_vec(), v.into_vec()))
+            .collect();
+        let changes = self.changes.read().unwrap();
+        for ((_, key), value) in changes.range((col, prefix.to_vec())..) {
+            if !key.starts_with(prefix) {
+                break;
+            }
+            match value {
+                Some(value) => merged.insert(key.clone(), value.clone()),
+                None => merged.remove(key),
+            };
+        }
+        merged
+    }
+}
+
+impl MallocSizeOf for OverlayDB {
+    fn size_of(&self, ops: &mut MallocSizeOfOps) -> usize {
+        self.backend.size_of(ops)
+    }
+}
+
+impl KeyValueDB for OverlayDB {
+    fn get(&self, col: u32, key: &[u8]) -> io::Result<Option<DBValue>> {
+        if let Some(value) = self.changes.read().unwrap().get(&(col, key.to_vec())) {
+            return Ok(value.clone());
+        }
+        self.backend.get(col, key)
+    }
+
+    fn get_by_prefix(&self, col: u32, prefix: &[u8]) -> Option<Box<[u8]>> {
+        self.merged(col, prefix)
+            .into_iter()
+            .next()
+            .map(|(_, v)| v.into_boxed_slice())
+    }
+
+    fn write(&self, transaction: DBTransaction) -> io::Result<()> {
+        for op in transaction.ops {
+            match op {
+                DBOp::Insert { col, key, value } => {
+                    self.changes
+                        .write()
+                        .unwrap()
+                        .insert((col, key.to_vec()), Some(value));
+                }
+                DBOp::Delete { col, key } => {
+                    self.changes
+                        .write()
+                        .unwrap()
+                        .insert((col, key.to_vec()), None);
+                }
+                DBOp::DeletePrefix { col, prefix } => {
+                    let keys: Vec<Vec<u8>> = self.merged(col, &prefix).into_keys().collect();
+                    let mut changes = self.changes.write().unwrap();
+                    for key in keys {
+                        changes.insert((col, key), None);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn iter<'a>(&'a self, col: u32) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+        self.iter_with_prefix(col, &[])
+    }
+
+    fn iter_with_prefix<'a>(
+        &'a self,
+        col: u32,
+        prefix: &'a [u8],
+    ) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+        Box::new(
+            self.merged(col, prefix)
+                .into_iter()
+                .map(|(k, v)| (k.into_boxed_slice(), v.into_boxed_slice())),
+        )
+    }
+
+    fn restore(&self, _new_db: &str) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "overlay database can't be restored",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database;
+
+    #[test]
+    fn test_overlay_drain() {
+        let dir = tempfile::Builder::new()
+            .prefix("worker_test")
+            .tempdir()
+            .unwrap();
+        let path = dir.path().join("db");
+        let backend =
+            database::open_database(path.to_str().unwrap()).expect("open database failed");
+        let overlay = OverlayDB::new(backend.clone());
+
+        let mut tx = overlay.transaction();
+        tx.put(0, b"hello", b"world");
+        overlay.write(tx).unwrap();
+        assert_eq!(overlay.get(0, b"hello").unwrap(), Some(b"world".to_vec()));
+        assert_eq!(backend.get(0, b"hello").unwrap(), None);
+
+        let mut tx = backend.transaction();
+        overlay.drain_into(&mut tx);
+        backend.write(tx).unwrap();
+        assert_eq!(backend.get(0, b"hello").unwrap(), Some(b"world".to_vec()));
+    }
+}
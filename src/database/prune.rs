@@ -0,0 +1,65 @@
+use std::{collections::HashSet, sync::Arc};
+
+use hash_db::HashDB;
+use kvdb::{DBValue, KeyValueDB};
+use trie_db::{
+    node::{Node, Value},
+    TrieDB, TrieDBNodeIterator,
+};
+
+use super::{db, error::Result, trie_layout::ExtensionLayout, AppDB, KEY_ROOT};
+
+impl AppDB {
+    /// `collect_node_keys` add the database keys of every trie node and value node reachable
+    /// from the root, a missing value node is an error.
+    pub fn collect_node_keys(&self, keys: &mut HashSet<Vec<u8>>) -> Result<()> {
+        let trie = TrieDB::<ExtensionLayout>::new(&self.db, &self.root)?;
+        for item in TrieDBNodeIterator::new(&trie)? {
+            let (prefix, hash, node) = item?;
+            // inline nodes are stored in their parent node
+            if let Some(hash) = hash {
+                keys.insert(db::prefixed_key(&hash, prefix.as_prefix()));
+            }
+
+            // value nodes are stored under the full key of their value
+            let (key, value) = match node.node() {
+                Node::Leaf(partial, value) => {
+                    let mut key = prefix.clone();
+                    key.append_partial(partial.right());
+                    (key, value)
+                }
+                Node::Branch(_, Some(value)) => (prefix.clone(), value),
+                _ => continue,
+            };
+            if let Value::Node(hash, ..) = value {
+                let mut value_hash = [0u8; 32];
+                value_hash.copy_from_slice(hash);
+                if !HashDB::<_, DBValue>::contains(&self.db, &value_hash, key.as_prefix()) {
+                    return Err(format!("value node {:?} is missing", value_hash).into());
+                }
+                keys.insert(db::prefixed_key(&value_hash, key.as_prefix()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `sweep_column` delete every trie node of the column not kept in one transaction,
+/// return the number of deleted nodes.
+pub fn sweep_column(
+    db_backend: &Arc<dyn KeyValueDB>,
+    column: u32,
+    keep: &HashSet<Vec<u8>>,
+) -> Result<usize> {
+    let mut tx = db_backend.transaction();
+    let mut deleted = 0;
+    for (key, _) in db_backend.iter(column) {
+        if key.as_ref() == KEY_ROOT || keep.contains(key.as_ref()) {
+            continue;
+        }
+        tx.delete(column, &key);
+        deleted += 1;
+    }
+    db_backend.write(tx)?;
+    Ok(deleted)
+}
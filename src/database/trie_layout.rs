@@ -11,40 +11,102 @@ use trie_db::{
 /// Reference hasher is a keccak hasher.
 pub type RefHasher = keccak_hasher::KeccakHasher;
 
+/// Values at least this long are stored by hash outside the trie nodes. The threshold changes
+/// the state roots, so it is a chain parameter and not part of the node config, this is the
+/// default of the chain parameter.
+pub const INLINE_VALUE_THRESHOLD: u32 = 128;
+
+/// The inline value thresholds a chain can choose, the layout takes the threshold at compile
+/// time.
+pub const INLINE_VALUE_THRESHOLDS: [u32; 4] = [32, 64, 128, 256];
+
+/// The longest partial key of a trie node in nibbles, `AppDB` refuses longer keys.
+pub const NIBBLE_SIZE_BOUND: usize = u16::MAX as usize;
+
 const EMPTY_TRIE: u8 = 0;
 const LEAF_NODE_OFFSET: u8 = 1;
 const EXTENSION_NODE_OFFSET: u8 = 128;
+const HASHED_VALUE_LEAF_NODE: u8 = 252;
+const BRANCH_NODE_WITH_HASHED_VALUE: u8 = 253;
 const BRANCH_NODE_NO_VALUE: u8 = 254;
 const BRANCH_NODE_WITH_VALUE: u8 = 255;
-const LEAF_NODE_OVER: u8 = EXTENSION_NODE_OFFSET - LEAF_NODE_OFFSET;
-const EXTENSION_NODE_OVER: u8 = BRANCH_NODE_NO_VALUE - EXTENSION_NODE_OFFSET;
+// the last header byte of a node type is followed by the rest of the nibble count
 const LEAF_NODE_LAST: u8 = EXTENSION_NODE_OFFSET - 1;
-const EXTENSION_NODE_LAST: u8 = BRANCH_NODE_NO_VALUE - 1;
+const EXTENSION_NODE_LAST: u8 = HASHED_VALUE_LEAF_NODE - 1;
 
 /// Simple reference implementation of a `NodeCodec`.
 #[derive(Default, Clone)]
 pub struct ReferenceNodeCodec<H>(PhantomData<H>);
 
+/// `ExtensionLayout` the trie layout of the chain, the inline value threshold only matters to the
+/// trie writes, every layout reads the nodes of the others.
 #[derive(Default, Clone)]
-pub struct ExtensionLayout;
+pub struct ExtensionLayout<const MAX_INLINE: u32 = INLINE_VALUE_THRESHOLD>;
 
-impl TrieLayout for ExtensionLayout {
+impl<const MAX_INLINE: u32> TrieLayout for ExtensionLayout<MAX_INLINE> {
     const USE_EXTENSION: bool = true;
     const ALLOW_EMPTY: bool = false;
-    const MAX_INLINE_VALUE: Option<u32> = None;
+    const MAX_INLINE_VALUE: Option<u32> = Some(MAX_INLINE);
     type Hash = RefHasher;
     type Codec = ReferenceNodeCodec<RefHasher>;
 }
 
-impl TrieConfiguration for ExtensionLayout {}
+impl<const MAX_INLINE: u32> TrieConfiguration for ExtensionLayout<MAX_INLINE> {}
+
+/// `check_inline_threshold` return an error if the chain can't choose the threshold.
+pub fn check_inline_threshold(threshold: u32) -> super::error::Result<()> {
+    if !INLINE_VALUE_THRESHOLDS.contains(&threshold) {
+        return Err(format!(
+            "unsupported inline value threshold {}, expected one of {:?}",
+            threshold, INLINE_VALUE_THRESHOLDS
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// `with_layout` run the body with the layout type of the inline value threshold, an
+/// unsupported threshold returns an error.
+macro_rules! with_layout {
+    ($threshold: expr, $layout: ident, $body: block) => {
+        match $threshold {
+            32 => {
+                type $layout = $crate::database::trie_layout::ExtensionLayout<32>;
+                $body
+            }
+            64 => {
+                type $layout = $crate::database::trie_layout::ExtensionLayout<64>;
+                $body
+            }
+            128 => {
+                type $layout = $crate::database::trie_layout::ExtensionLayout<128>;
+                $body
+            }
+            256 => {
+                type $layout = $crate::database::trie_layout::ExtensionLayout<256>;
+                $body
+            }
+            threshold => Err(format!("unsupported inline value threshold {}", threshold).into()),
+        }
+    };
+}
+
+pub(crate) use with_layout;
 
-/// A node header.
+/// A node header with the nibble count of the partial key.
+///
+/// A leaf or extension header byte holds the nibble count up to the last byte of its range, the
+/// last byte is followed by the rest of the count in continuation bytes, as the `NodeHeader` of
+/// Substrate does: every 255 byte adds 255 and the first smaller byte ends the count. The hashed
+/// value leaf header is followed by its whole nibble count in continuation bytes.
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 enum NodeHeader {
     Null,
     Branch(bool),
+    HashedValueBranch,
     Extension(usize),
     Leaf(usize),
+    HashedValueLeaf(usize),
 }
 
 impl Encode for NodeHeader {
@@ -53,11 +115,19 @@ impl Encode for NodeHeader {
             NodeHeader::Null => output.push_byte(EMPTY_TRIE),
             NodeHeader::Branch(true) => output.push_byte(BRANCH_NODE_WITH_VALUE),
             NodeHeader::Branch(false) => output.push_byte(BRANCH_NODE_NO_VALUE),
+            NodeHeader::HashedValueBranch => output.push_byte(BRANCH_NODE_WITH_HASHED_VALUE),
             NodeHeader::Leaf(nibble_count) => {
-                output.push_byte(LEAF_NODE_OFFSET + *nibble_count as u8)
+                encode_size(output, LEAF_NODE_OFFSET, LEAF_NODE_LAST, *nibble_count)
             }
-            NodeHeader::Extension(nibble_count) => {
-                output.push_byte(EXTENSION_NODE_OFFSET + *nibble_count as u8)
+            NodeHeader::Extension(nibble_count) => encode_size(
+                output,
+                EXTENSION_NODE_OFFSET,
+                EXTENSION_NODE_LAST,
+                *nibble_count,
+            ),
+            NodeHeader::HashedValueLeaf(nibble_count) => {
+                output.push_byte(HASHED_VALUE_LEAF_NODE);
+                encode_continuation(output, *nibble_count);
             }
         }
     }
@@ -69,6 +139,16 @@ impl Decode for NodeHeader {
             EMPTY_TRIE => NodeHeader::Null,
             BRANCH_NODE_NO_VALUE => NodeHeader::Branch(false),
             BRANCH_NODE_WITH_VALUE => NodeHeader::Branch(true),
+            BRANCH_NODE_WITH_HASHED_VALUE => NodeHeader::HashedValueBranch,
+            HASHED_VALUE_LEAF_NODE => NodeHeader::HashedValueLeaf(decode_continuation(input, 0)?),
+            LEAF_NODE_LAST => NodeHeader::Leaf(decode_continuation(
+                input,
+                (LEAF_NODE_LAST - LEAF_NODE_OFFSET) as usize,
+            )?),
+            EXTENSION_NODE_LAST => NodeHeader::Extension(decode_continuation(
+                input,
+                (EXTENSION_NODE_LAST - EXTENSION_NODE_OFFSET) as usize,
+            )?),
             i @ LEAF_NODE_OFFSET..=LEAF_NODE_LAST => {
                 NodeHeader::Leaf((i - LEAF_NODE_OFFSET) as usize)
             }
@@ -79,6 +159,41 @@ impl Decode for NodeHeader {
     }
 }
 
+/// `encode_size` the nibble count in the header byte from `offset` on, a count reaching `last`
+/// continues in the following bytes.
+fn encode_size<T: Output + ?Sized>(output: &mut T, offset: u8, last: u8, nibble_count: usize) {
+    let in_header = (last - offset) as usize;
+    if nibble_count < in_header {
+        output.push_byte(offset + nibble_count as u8);
+    } else {
+        output.push_byte(last);
+        encode_continuation(output, nibble_count - in_header);
+    }
+}
+
+fn encode_continuation<T: Output + ?Sized>(output: &mut T, mut count: usize) {
+    while count >= u8::MAX as usize {
+        output.push_byte(u8::MAX);
+        count -= u8::MAX as usize;
+    }
+    output.push_byte(count as u8);
+}
+
+/// `decode_continuation` add the continuation bytes to the count, a count over
+/// `NIBBLE_SIZE_BOUND` is an error.
+fn decode_continuation<I: Input>(input: &mut I, mut count: usize) -> Result<usize, CodecError> {
+    loop {
+        let byte = input.read_byte()?;
+        count += byte as usize;
+        if count > NIBBLE_SIZE_BOUND {
+            return Err("nibble count over bound".into());
+        }
+        if byte < u8::MAX {
+            return Ok(count);
+        }
+    }
+}
+
 struct ByteSliceInput<'a> {
     data: &'a [u8],
     offset: usize,
@@ -167,15 +282,17 @@ impl<H: Hasher> NodeCodec for ReferenceNodeCodec<H> {
         let mut input = ByteSliceInput::new(data);
         match NodeHeader::decode(&mut input)? {
             NodeHeader::Null => Ok(NodePlan::Empty),
-            NodeHeader::Branch(has_value) => {
+            header @ (NodeHeader::Branch(_) | NodeHeader::HashedValueBranch) => {
                 let bitmap_range = input.take(BITMAP_LENGTH)?;
                 let bitmap = Bitmap::decode(&data[bitmap_range])?;
 
-                let value = if has_value {
-                    let count = <Compact<u32>>::decode(&mut input)?.0 as usize;
-                    Some(ValuePlan::Inline(input.take(count)?))
-                } else {
-                    None
+                let value = match header {
+                    NodeHeader::Branch(true) => {
+                        let count = <Compact<u32>>::decode(&mut input)?.0 as usize;
+                        Some(ValuePlan::Inline(input.take(count)?))
+                    }
+                    NodeHeader::HashedValueBranch => Some(ValuePlan::Node(input.take(H::LENGTH)?)),
+                    _ => None,
                 };
                 let mut children = [
                     None, None, None, None, None, None, None, None, None, None, None, None, None,
@@ -212,17 +329,22 @@ impl<H: Hasher> NodeCodec for ReferenceNodeCodec<H> {
                     child,
                 })
             }
-            NodeHeader::Leaf(nibble_count) => {
+            header @ (NodeHeader::Leaf(nibble_count)
+            | NodeHeader::HashedValueLeaf(nibble_count)) => {
                 let partial = input.take(
                     (nibble_count + (nibble_ops::NIBBLE_PER_BYTE - 1))
                         / nibble_ops::NIBBLE_PER_BYTE,
                 )?;
                 let partial_padding = nibble_ops::number_padding(nibble_count);
-                let count = <Compact<u32>>::decode(&mut input)?.0 as usize;
-                let value = input.take(count)?;
+                let value = if let NodeHeader::HashedValueLeaf(_) = header {
+                    ValuePlan::Node(input.take(H::LENGTH)?)
+                } else {
+                    let count = <Compact<u32>>::decode(&mut input)?.0 as usize;
+                    ValuePlan::Inline(input.take(count)?)
+                };
                 Ok(NodePlan::Leaf {
                     partial: NibbleSlicePlan::new(partial, partial_padding),
-                    value: ValuePlan::Inline(value),
+                    value,
                 })
             }
         }
@@ -237,15 +359,19 @@ impl<H: Hasher> NodeCodec for ReferenceNodeCodec<H> {
     }
 
     fn leaf_node(partial: Partial, value: Value) -> Vec<u8> {
-        let mut output = partial_to_key(partial, LEAF_NODE_OFFSET, LEAF_NODE_OVER);
         match value {
             Value::Inline(value) => {
+                let mut output = partial_to_key(partial, NodeHeader::Leaf);
                 Compact(value.len() as u32).encode_to(&mut output);
                 output.extend_from_slice(value);
+                output
+            }
+            Value::Node(hash, ..) => {
+                let mut output = partial_to_key(partial, NodeHeader::HashedValueLeaf);
+                output.extend_from_slice(hash);
+                output
             }
-            _ => unimplemented!("unsupported"),
         }
-        output
     }
 
     fn extension_node(
@@ -253,12 +379,8 @@ impl<H: Hasher> NodeCodec for ReferenceNodeCodec<H> {
         number_nibble: usize,
         child: ChildReference<Self::HashOut>,
     ) -> Vec<u8> {
-        let mut output = partial_from_iterator_to_key(
-            partial,
-            number_nibble,
-            EXTENSION_NODE_OFFSET,
-            EXTENSION_NODE_OVER,
-        );
+        let mut output =
+            partial_from_iterator_to_key(partial, number_nibble, NodeHeader::Extension);
         match child {
             ChildReference::Hash(h) => h.as_ref().encode_to(&mut output),
             ChildReference::Inline(inline_data, len) => {
@@ -274,14 +396,17 @@ impl<H: Hasher> NodeCodec for ReferenceNodeCodec<H> {
     ) -> Vec<u8> {
         let mut output = vec![0; BITMAP_LENGTH + 1];
         let mut prefix: [u8; 3] = [0; 3];
-        let have_value = match maybe_value {
+        let header = match maybe_value {
             Some(Value::Inline(value)) => {
                 Compact(value.len() as u32).encode_to(&mut output);
                 output.extend_from_slice(value);
-                true
+                BRANCH_NODE_WITH_VALUE
             }
-            None => false,
-            _ => unimplemented!("unsupported"),
+            Some(Value::Node(hash, ..)) => {
+                output.extend_from_slice(hash);
+                BRANCH_NODE_WITH_HASHED_VALUE
+            }
+            None => BRANCH_NODE_NO_VALUE,
         };
         let has_children = children.map(|maybe_child| match maybe_child.borrow() {
             Some(ChildReference::Hash(h)) => {
@@ -294,7 +419,7 @@ impl<H: Hasher> NodeCodec for ReferenceNodeCodec<H> {
             }
             None => false,
         });
-        branch_node_buffered(have_value, has_children, prefix.as_mut());
+        branch_node_buffered(header, has_children, prefix.as_mut());
         output[0..BITMAP_LENGTH + 1].copy_from_slice(prefix.as_ref());
         output
     }
@@ -309,11 +434,10 @@ impl<H: Hasher> NodeCodec for ReferenceNodeCodec<H> {
     }
 }
 
-fn partial_to_key(partial: Partial, offset: u8, over: u8) -> Vec<u8> {
+fn partial_to_key(partial: Partial, header: fn(usize) -> NodeHeader) -> Vec<u8> {
     let number_nibble_encoded = (partial.0).0 as usize;
     let nibble_count = partial.1.len() * nibble_ops::NIBBLE_PER_BYTE + number_nibble_encoded;
-    assert!(nibble_count < over as usize);
-    let mut output = vec![offset + nibble_count as u8];
+    let mut output = header(nibble_count).encode();
     if number_nibble_encoded > 0 {
         output.push(nibble_ops::pad_right((partial.0).1));
     }
@@ -324,28 +448,17 @@ fn partial_to_key(partial: Partial, offset: u8, over: u8) -> Vec<u8> {
 fn partial_from_iterator_to_key<I: Iterator<Item = u8>>(
     partial: I,
     nibble_count: usize,
-    offset: u8,
-    over: u8,
+    header: fn(usize) -> NodeHeader,
 ) -> Vec<u8> {
-    assert!(nibble_count < over as usize);
-    let mut output = Vec::with_capacity(1 + (nibble_count / nibble_ops::NIBBLE_PER_BYTE));
-    output.push(offset + nibble_count as u8);
+    let mut output = Vec::with_capacity(3 + (nibble_count / nibble_ops::NIBBLE_PER_BYTE));
+    header(nibble_count).encode_to(&mut output);
     output.extend(partial);
     output
 }
 
 /// Encoding of branch header and children bitmap for any radix.
 /// For codec/stream variant with extension.
-fn branch_node_buffered<I: Iterator<Item = bool>>(
-    has_value: bool,
-    has_children: I,
-    output: &mut [u8],
-) {
-    let first = if has_value {
-        BRANCH_NODE_WITH_VALUE
-    } else {
-        BRANCH_NODE_NO_VALUE
-    };
-    output[0] = first;
+fn branch_node_buffered<I: Iterator<Item = bool>>(header: u8, has_children: I, output: &mut [u8]) {
+    output[0] = header;
     Bitmap::encode(has_children, &mut output[1..]);
 }
@@ -10,6 +10,8 @@ pub struct Directories {
     pub base: String,
     /// Database dir
     pub db: String,
+    /// Discovered peers file
+    pub peers: String,
 }
 
 impl Directories {
@@ -20,6 +22,11 @@ impl Directories {
                 .to_str()
                 .unwrap()
                 .to_string(),
+            peers: Path::new(&base_path)
+                .join("peers.json")
+                .to_str()
+                .unwrap()
+                .to_string(),
             base: base_path,
         }
     }
@@ -34,6 +41,7 @@ impl Default for Directories {
         Directories {
             base: data_dir.to_str().unwrap().to_string(),
             db: data_dir.join("database").to_str().unwrap().to_string(),
+            peers: data_dir.join("peers.json").to_str().unwrap().to_string(),
         }
     }
 }
@@ -1,5 +1,5 @@
 use ic_agent::{ic_types::Principal, Identity, Signature};
-use libp2p::identity::ed25519::Keypair;
+use libp2p::identity::ed25519::{Keypair, PublicKey};
 use simple_asn1::{
     oid, to_der,
     ASN1Block::{BitString, ObjectIdentifier, Sequence},
@@ -26,6 +26,22 @@ impl WdnIdentity {
     }
 }
 
+/// Length of the DER prefix written by `der_encode_public_key` in front of an Ed25519 key.
+const DER_ED25519_PREFIX_LEN: usize = 12;
+
+/// `public_key_from_der` recover the raw Ed25519 public key from a DER encoded one.
+pub fn public_key_from_der(der_encoded_public_key: &[u8]) -> Option<PublicKey> {
+    if der_encoded_public_key.len() != DER_ED25519_PREFIX_LEN + 32 {
+        return None;
+    }
+    PublicKey::decode(&der_encoded_public_key[DER_ED25519_PREFIX_LEN..]).ok()
+}
+
+/// `principal_from_der` derive the self authenticating principal of a DER encoded public key.
+pub fn principal_from_der(der_encoded_public_key: &[u8]) -> Principal {
+    Principal::self_authenticating(der_encoded_public_key)
+}
+
 impl Identity for WdnIdentity {
     fn sender(&self) -> Result<Principal, String> {
         Ok(Principal::self_authenticating(&self.der_encoded_public_key))
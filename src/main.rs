@@ -1,19 +1,23 @@
 use chrono::Local;
+use clap::Parser;
 use database::data_types::NodeActiveStatus;
 use database::AppDB;
 use env_logger::{Builder, Env};
 use error::WError;
 use futures::channel::mpsc::{channel, SendError, Sender};
 use futures::{SinkExt, StreamExt};
+use kvdb::KeyValueDB;
 use libp2p::identity::Keypair;
 use message::{Caller, LocalMessage, Message};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
 use crate::error::ErrorCode;
 use crate::{message::LocalMessageModule, task::TaskModule};
 
 mod api;
 mod blockchain;
+mod command;
 mod config;
 mod console;
 mod dao;
@@ -47,7 +51,11 @@ async fn main() {
 }
 
 async fn init() -> Result<(), WError> {
+    let cli = command::Cli::parse();
     let conf = config::load_config("config.toml".to_string()).unwrap();
+    if let Some(command) = &cli.command {
+        return command::run_command(command, &conf);
+    }
 
     if conf.node_config.principal_id.is_empty() {
         println!("Please set principal id of network config on config.toml");
@@ -63,10 +71,19 @@ async fn init() -> Result<(), WError> {
     let local_key = key_pair::new(conf.base.data_path.clone())?;
 
     // database
-    let db_backend = database::open_database(d.db.as_str()).expect("open database failed");
+    let db_backend =
+        database::open_backend(conf.base.db_backend, d.db.as_str()).expect("open database failed");
+    let db_cache = database::cache::CachedDB::new(db_backend, conf.base.trie_cache_bytes());
+    let db_backend: Arc<dyn KeyValueDB> = db_cache.clone();
 
     // blockchain module
-    let mut blockchain_module = blockchain::BlockchainModule::new(db_backend.clone())?;
+    blockchain::prune::set_journaling(&db_backend, conf.base.prune_depth().is_some())?;
+    let mut blockchain_module = blockchain::BlockchainModule::new(
+        db_backend.clone(),
+        local_key.clone(),
+        conf.blockchain_config.clone(),
+    )?;
+    blockchain_module.prune_depth = conf.base.prune_depth();
     let blockchain_module_caller = blockchain_module.get_message_caller();
 
     // node module
@@ -85,9 +102,11 @@ async fn init() -> Result<(), WError> {
     )?;
     let task_caller = task_module.get_message_caller();
     node_module.task_caller = Some(task_caller.clone());
+    blockchain_module.node_caller = Some(node_caller.clone());
+    blockchain_module.task_caller = Some(task_caller.clone());
 
     // Join P2P network.
-    let mut net_moudle = network::Network::new(conf.network, local_key.clone());
+    let mut net_moudle = network::Network::new(conf.network, local_key.clone(), d.peers.clone());
     net_moudle.add_module(&mut node_module);
     net_moudle.add_module(&mut task_module);
     net_moudle.add_module(&mut blockchain_module);
@@ -117,7 +136,10 @@ async fn init() -> Result<(), WError> {
     }
 
     // api module
-    let api_module = api::ApiModule::new(node_caller.clone(), conf.api_config.clone());
+    let mut api_module = api::ApiModule::new(node_caller.clone(), conf.api_config.clone());
+    api_module.blockchain_caller = Some(blockchain_module_caller.clone());
+    api_module.task_caller = Some(task_caller.clone());
+    api_module.db_cache = Some(db_cache);
     let _ = api::run(api_module).await;
     Ok(())
 }
@@ -4,8 +4,15 @@ use serde::Serialize;
 use std::{collections::HashMap, error::Error, fmt, pin::Pin, result, thread};
 
 use crate::{
-    blockchain::db::{ActivationOperation, Block, NeedSignData, NodeActivation, TaskOperation},
-    database::data_types::{NodeActiveStatus, TaskData},
+    blockchain::{
+        db::{
+            ActivationOperation, Block, Header, NeedSignData, NodeActivation, Reward, TaskOperation,
+        },
+        finality::FinalityStatus,
+        proof::{HeaderStateProof, StateTrie},
+        sync::SyncStatus,
+    },
+    database::data_types::{NodeActiveStatus, NodeData, TaskData},
     module_quick_from,
     network::NetworkMessage,
 };
@@ -25,6 +32,10 @@ pub enum LocalMessage {
     InvokeTask(),
     GetTaskList(),
     GetTaskListResponse(Vec<TaskData>),
+    ReqTaskPage(Option<u64>, usize),
+    AckTaskPage(Vec<TaskData>),
+    ReqNodePage(Option<String>, usize),
+    AckNodePage(Vec<NodeData>),
     ReqKeeperInit(),
     AckKeeperInit(bool),
     ReqWorkerActive(),
@@ -41,9 +52,33 @@ pub enum LocalMessage {
     ReqBlockCurrent(),
     AckBlockCurrent(Block),
     ReqBlockPack(),
-    ReqTaskInitGenesis(),
-    AckTaskInitGenesis(bool),
     BlockTick(),
+    BlockSyncTick(),
+    DiscoveryTick(),
+    ReqBlockSyncStatus(),
+    AckBlockSyncStatus(SyncStatus),
+    ReqBlockRewards(u64),
+    AckBlockRewards(Vec<Reward>),
+    ReqBlockFinality(),
+    AckBlockFinality(FinalityStatus),
+    BlockNewHead(Header),
+    ReqStateProof(StateTrie, Vec<Vec<u8>>),
+    AckStateProof(HeaderStateProof),
+    ReqHistoryTasks(u64, Option<u64>, usize),
+    ReqHistoryNodes(u64, Option<String>, usize),
+    ReqHistoryBalance(u64, Vec<u8>),
+    AckHistoryBalance(u64),
+    AckError(ReqError),
+}
+
+/// `ReqError` the answer to a request that failed, so the caller never waits for an answer
+/// that won't come. The api maps the kind to the http status.
+#[derive(Debug, PartialEq, Clone, Eq, Serialize)]
+pub enum ReqError {
+    NotFound(String),
+    Gone(String),
+    Invalid(String),
+    Internal(String),
 }
 
 pub trait LocalMessageModule {
@@ -5,4 +5,14 @@ use toml::value::*;
 pub struct NetworkConfig {
     pub port: u32,
     pub known_nodes: Array,
+    /// Find the peers of the local network by mDNS, for devnets.
+    #[serde(default)]
+    pub mdns: bool,
+    /// Peers the node keeps connected to, more are looked up in the DHT below it.
+    #[serde(default = "default_target_peers")]
+    pub target_peers: usize,
+}
+
+fn default_target_peers() -> usize {
+    8
 }
@@ -0,0 +1,193 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use libp2p::{
+    gossipsub::{Gossipsub, GossipsubEvent},
+    kad::{store::MemoryStore, Kademlia, KademliaConfig, KademliaEvent},
+    mdns::{Mdns, MdnsConfig, MdnsEvent},
+    multiaddr::Protocol,
+    swarm::toggle::Toggle,
+    Multiaddr, NetworkBehaviour, PeerId,
+};
+
+/// Kademlia protocol of the worker network, kept apart from the public IPFS DHT.
+pub const KAD_PROTOCOL_NAME: &[u8] = b"/wdn/kad/1.0.0";
+
+/// Milliseconds between two checks of the peer count.
+pub const DISCOVERY_TICK_INTERVAL_MILLIS: u64 = 30_000;
+
+/// Most peers kept in the peer file.
+pub const MAX_STORED_PEERS: usize = 256;
+
+/// `Behaviour` gossipsub for the module messages, Kademlia and the optional mDNS to find peers.
+#[derive(NetworkBehaviour)]
+#[behaviour(out_event = "BehaviourEvent", event_process = false)]
+pub struct Behaviour {
+    pub gossipsub: Gossipsub,
+    pub kademlia: Kademlia<MemoryStore>,
+    pub mdns: Toggle<Mdns>,
+}
+
+#[derive(Debug)]
+pub enum BehaviourEvent {
+    Gossipsub(GossipsubEvent),
+    Kademlia(KademliaEvent),
+    Mdns(MdnsEvent),
+}
+
+impl From<GossipsubEvent> for BehaviourEvent {
+    fn from(event: GossipsubEvent) -> Self {
+        BehaviourEvent::Gossipsub(event)
+    }
+}
+
+impl From<KademliaEvent> for BehaviourEvent {
+    fn from(event: KademliaEvent) -> Self {
+        BehaviourEvent::Kademlia(event)
+    }
+}
+
+impl From<MdnsEvent> for BehaviourEvent {
+    fn from(event: MdnsEvent) -> Self {
+        BehaviourEvent::Mdns(event)
+    }
+}
+
+impl Behaviour {
+    pub async fn new(
+        gossipsub: Gossipsub,
+        local_peer_id: PeerId,
+        enable_mdns: bool,
+    ) -> io::Result<Behaviour> {
+        let mut kademlia_config = KademliaConfig::default();
+        kademlia_config.set_protocol_name(KAD_PROTOCOL_NAME);
+        let kademlia = Kademlia::with_config(
+            local_peer_id,
+            MemoryStore::new(local_peer_id),
+            kademlia_config,
+        );
+
+        let mdns = if enable_mdns {
+            Some(Mdns::new(MdnsConfig::default()).await?)
+        } else {
+            None
+        };
+        Ok(Behaviour {
+            gossipsub,
+            kademlia,
+            mdns: Toggle::from(mdns),
+        })
+    }
+}
+
+/// `split_peer_id` split the trailing `/p2p/<peer id>` off the address.
+pub fn split_peer_id(address: &Multiaddr) -> Option<(PeerId, Multiaddr)> {
+    let mut address = address.clone();
+    match address.pop() {
+        Some(Protocol::P2p(hash)) => PeerId::from_multihash(hash)
+            .ok()
+            .map(|peer_id| (peer_id, address)),
+        _ => None,
+    }
+}
+
+/// `PeerStore` the addresses of the discovered peers, saved in the data directory so the next
+/// start doesn't depend on the known nodes alone.
+pub struct PeerStore {
+    path: PathBuf,
+    peers: HashMap<PeerId, Multiaddr>,
+    changed: bool,
+}
+
+impl PeerStore {
+    /// `load` read the peer file, a missing or broken file is an empty store.
+    pub fn load(path: &Path) -> PeerStore {
+        let mut store = PeerStore {
+            path: path.to_path_buf(),
+            peers: HashMap::new(),
+            changed: false,
+        };
+        let addresses: Vec<String> = match fs::read_to_string(path) {
+            Ok(data) => serde_json::from_str(&data).unwrap_or_else(|e| {
+                log::error!("invalid peer file {:?}: {:?}", path, e);
+                vec![]
+            }),
+            Err(_) => vec![],
+        };
+        for address in addresses {
+            let split = address.parse().ok().and_then(|a| split_peer_id(&a));
+            if let Some((peer_id, address)) = split {
+                store.peers.insert(peer_id, address);
+            }
+        }
+        store
+    }
+
+    pub fn insert(&mut self, peer_id: PeerId, address: Multiaddr) {
+        if self.peers.len() >= MAX_STORED_PEERS && !self.peers.contains_key(&peer_id) {
+            return;
+        }
+        if self.peers.get(&peer_id) != Some(&address) {
+            self.peers.insert(peer_id, address);
+            self.changed = true;
+        }
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = (&PeerId, &Multiaddr)> {
+        self.peers.iter()
+    }
+
+    /// `save` write the peer file if a peer changed since the last save.
+    pub fn save(&mut self) -> Result<(), Box<dyn Error>> {
+        if !self.changed {
+            return Ok(());
+        }
+        let addresses: Vec<String> = self
+            .peers
+            .iter()
+            .map(|(peer_id, address)| {
+                address
+                    .clone()
+                    .with(Protocol::P2p((*peer_id).into()))
+                    .to_string()
+            })
+            .collect();
+        fs::write(&self.path, serde_json::to_string(&addresses)?)?;
+        self.changed = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peer_store_reload() {
+        let dir = tempfile::Builder::new()
+            .prefix("worker_test")
+            .tempdir()
+            .unwrap();
+        let path = dir.path().join("peers.json");
+        let peer_id = PeerId::random();
+        let address: Multiaddr = "/ip4/127.0.0.1/tcp/9000".parse().unwrap();
+
+        let mut store = PeerStore::load(&path);
+        assert_eq!(store.peers().count(), 0);
+        store.insert(peer_id, address.clone());
+        store.save().unwrap();
+
+        let store = PeerStore::load(&path);
+        let peers: Vec<(&PeerId, &Multiaddr)> = store.peers().collect();
+        assert_eq!(peers, vec![(&peer_id, &address)]);
+        assert_eq!(
+            split_peer_id(&address.clone().with(Protocol::P2p(peer_id.into()))),
+            Some((peer_id, address.clone()))
+        );
+        assert_eq!(split_peer_id(&address), None);
+    }
+}
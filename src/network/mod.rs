@@ -1,6 +1,7 @@
 use std::collections::hash_map::DefaultHasher;
 use std::error::Error;
 use std::hash::{Hash, Hasher};
+use std::path::Path;
 use std::thread;
 use std::time::Duration;
 
@@ -11,13 +12,18 @@ use futures::{prelude::*, select};
 use libp2p::gossipsub::{GossipsubEvent, GossipsubMessage, MessageAuthenticity, ValidationMode};
 use libp2p::gossipsub::{IdentTopic, MessageId, Topic};
 use libp2p::identity::Keypair;
-use libp2p::{gossipsub, swarm::SwarmEvent, Multiaddr, PeerId};
+use libp2p::kad::KademliaEvent;
+use libp2p::mdns::MdnsEvent;
+use libp2p::multiaddr::Protocol;
+use libp2p::{core::ConnectedPoint, gossipsub, swarm::SwarmEvent, Multiaddr, PeerId, Swarm};
 
-use crate::message::{Caller, InnerMessage, Message, Waiter};
+use crate::message::{Caller, InnerMessage, LocalMessage, Message, Waiter};
 
+use self::discovery::{Behaviour, BehaviourEvent, PeerStore};
 use self::topics::Topics;
 
 pub mod config;
+pub mod discovery;
 pub mod topics;
 use log;
 
@@ -41,16 +47,18 @@ pub trait NetworkModule {
 pub struct Network {
     conf: config::NetworkConfig,
     key: Keypair,
+    peers_path: String,
 
-    module_message_caller: HashMap<topics::Topics, Caller>,
+    module_message_caller: HashMap<topics::Topics, Vec<Caller>>,
     message_waiter: Waiter,
 }
 
 impl Network {
-    pub fn new(conf: config::NetworkConfig, key: Keypair) -> Network {
+    pub fn new(conf: config::NetworkConfig, key: Keypair, peers_path: String) -> Network {
         Network {
             conf,
             key,
+            peers_path,
 
             module_message_caller: HashMap::new(),
             message_waiter: Waiter::new(),
@@ -63,18 +71,59 @@ impl Network {
     {
         let sub = module.get_message_subscribe();
         for (topic, caller) in sub {
-            self.module_message_caller.insert(topic, caller);
+            self.module_message_caller
+                .entry(topic)
+                .or_insert_with(Vec::new)
+                .push(caller);
         }
         module.set_message_caller(self.message_waiter.get_caller());
     }
 }
 
 pub fn run(network: Network) {
+    let mut discovery_tick_caller = network.message_waiter.get_caller();
     thread::spawn(move || {
         task::block_on(async {
             message_loop(network).await.unwrap();
         })
     });
+
+    // discovery tick
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_millis(
+            discovery::DISCOVERY_TICK_INTERVAL_MILLIS,
+        ));
+        let res = task::block_on(
+            discovery_tick_caller.notify(Message::LocalMessage(LocalMessage::DiscoveryTick())),
+        );
+        if res.is_err() {
+            log::error!("network send discovery tick fail: {:?}", res);
+        }
+    });
+}
+
+/// `maintain_peers` dial stored peers while the node has less than the target peer count,
+/// and look up more peers in the DHT.
+fn maintain_peers(swarm: &mut Swarm<Behaviour>, peer_store: &PeerStore, target_peers: usize) {
+    let connected = swarm.network_info().num_peers();
+    if connected >= target_peers {
+        return;
+    }
+    let addresses: Vec<Multiaddr> = peer_store
+        .peers()
+        .filter(|(peer_id, _)| !swarm.is_connected(peer_id))
+        .take(target_peers - connected)
+        .map(|(peer_id, address)| address.clone().with(Protocol::P2p((*peer_id).into())))
+        .collect();
+    for address in addresses {
+        if let Err(e) = swarm.dial(address.clone()) {
+            log::info!("Dial {:?} failed: {:?}", address, e);
+        }
+    }
+    swarm
+        .behaviour_mut()
+        .kademlia
+        .get_closest_peers(PeerId::random());
 }
 
 async fn message_loop(mut network: Network) -> Result<(), Box<dyn Error>> {
@@ -118,23 +167,42 @@ async fn message_loop(mut network: Network) -> Result<(), Box<dyn Error>> {
         }
 
         // build the swarm
-        libp2p::Swarm::new(transport, gossipsub, local_peer_id)
+        let behaviour = Behaviour::new(gossipsub, local_peer_id, network.conf.mdns).await?;
+        libp2p::Swarm::new(transport, behaviour, local_peer_id)
     };
+    let mut peer_store = PeerStore::load(Path::new(&network.peers_path));
 
     // Listen on all interfaces
     let interface = format!("/ip4/0.0.0.0/tcp/{:?}", network.conf.port);
     swarm.listen_on(interface.parse().unwrap()).unwrap();
 
+    // Known nodes with a `/p2p/<peer id>` suffix bootstrap the DHT.
     for known_node in &network.conf.known_nodes {
         let known_node_str = known_node.as_str();
         if let Some(s) = known_node_str {
             let address: Multiaddr = s.parse().expect("User to provide valid address.");
+            if let Some((peer_id, peer_address)) = discovery::split_peer_id(&address) {
+                swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .add_address(&peer_id, peer_address);
+            }
             match swarm.dial(address.clone()) {
                 Ok(_) => log::info!("Dialed {:?}", address),
                 Err(e) => log::info!("Dial {:?} failed: {:?}", address, e),
             };
         }
     }
+    for (peer_id, address) in peer_store.peers() {
+        swarm
+            .behaviour_mut()
+            .kademlia
+            .add_address(peer_id, address.clone());
+    }
+    if let Err(e) = swarm.behaviour_mut().kademlia.bootstrap() {
+        log::info!("Kademlia bootstrap failed: {:?}", e);
+    }
+    maintain_peers(&mut swarm, &peer_store, network.conf.target_peers);
 
     // Kick it off
     loop {
@@ -142,39 +210,77 @@ async fn message_loop(mut network: Network) -> Result<(), Box<dyn Error>> {
             msg = network.message_waiter.next() => match msg {
                 Some(InnerMessage{ msg: Message::NetworkMessage(NetworkMessage{ peer_id, topic, message }), ..}) => {
                     let t: IdentTopic = Topic::new(topic);
-                    if let Err(e) = swarm.behaviour_mut().publish(t, message) {
+                    if let Err(e) = swarm.behaviour_mut().gossipsub.publish(t, message) {
                         log::info!("Publish error: {:?}", e);
                     }
                 },
+                Some(InnerMessage{ msg: Message::LocalMessage(LocalMessage::DiscoveryTick()), ..}) => {
+                    maintain_peers(&mut swarm, &peer_store, network.conf.target_peers);
+                    if let Err(e) = peer_store.save() {
+                        log::error!("save peers fail: {:?}", e);
+                    }
+                },
                 _ => {
                     log::info!("none")
                 }
             },
             event = swarm.select_next_some() => match event {
-                SwarmEvent::Behaviour(GossipsubEvent::Message {
+                SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(GossipsubEvent::Message {
                     propagation_source: _,
                     message_id: _,
                     message,
-                }) => {
+                })) => {
                     log::info!("reveive message {:?}", &message);
                     let topic: topics::Topics = message.clone().topic.into_string().into();
                     let chan = network.module_message_caller.get_mut(&topic);
                     match chan {
-                        Some(c) => {
+                        Some(callers) => {
                             log::info!("find dealer");
-                            let msg = Message::NetworkMessage(NetworkMessage{
-                                peer_id: message.source,
-                                topic,
-                                message: message.data,
-                            });
-                            let res = c.notify(msg).await;
-                            log::info!("{:?}", res);
+                            for c in callers {
+                                let msg = Message::NetworkMessage(NetworkMessage{
+                                    peer_id: message.source,
+                                    topic: topic.clone(),
+                                    message: message.data.clone(),
+                                });
+                                let res = c.notify(msg).await;
+                                log::info!("{:?}", res);
+                            }
                         },
                         None => {
                             log::info!("can not find dealer");
                         }
                     }
                 },
+                SwarmEvent::Behaviour(BehaviourEvent::Mdns(MdnsEvent::Discovered(peers))) => {
+                    for (peer_id, address) in peers {
+                        log::info!("mDNS discovered {:?} at {:?}", peer_id, address);
+                        swarm
+                            .behaviour_mut()
+                            .kademlia
+                            .add_address(&peer_id, address.clone());
+                        peer_store.insert(peer_id, address);
+                    }
+                    maintain_peers(&mut swarm, &peer_store, network.conf.target_peers);
+                },
+                SwarmEvent::Behaviour(BehaviourEvent::Kademlia(KademliaEvent::RoutingUpdated {
+                    peer,
+                    addresses,
+                    ..
+                })) => {
+                    peer_store.insert(peer, addresses.first().clone());
+                },
+                SwarmEvent::ConnectionEstablished {
+                    peer_id,
+                    endpoint: ConnectedPoint::Dialer { address, .. },
+                    ..
+                } => {
+                    log::info!("Connected to {:?} at {:?}", peer_id, address);
+                    let address = match discovery::split_peer_id(&address) {
+                        Some((_, address)) => address,
+                        None => address,
+                    };
+                    peer_store.insert(peer_id, address);
+                },
                 SwarmEvent::NewListenAddr { address, .. } => {
                     log::info!("Listening on {:?}", address);
                 },
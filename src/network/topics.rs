@@ -34,6 +34,12 @@ pub enum SubTopics {
     GetTaskListResponse(Vec<TaskData>),
     Ping,
     Pong,
+    NewBlock,
+    ReqSyncHead(i64),
+    AckSyncHead(u64),
+    ReqSyncBlocks(u64, u64, String, i64),
+    AckSyncBlocks,
+    Vote,
 }
 
 #[derive(Debug, PartialEq, Clone, Eq, Hash, Deserialize, Serialize)]
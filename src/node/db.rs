@@ -3,13 +3,24 @@ use ethereum_types::H256;
 use hash_db::Hasher;
 use keccak_hasher::KeccakHasher;
 use kvdb::KeyValueDB;
-use std::sync::Arc;
+use parity_scale_codec::{Decode, Encode};
+use std::{collections::BTreeMap, sync::Arc};
 
 use crate::{
-    blockchain::db::{NeedSignData, NodeActivation},
-    database::{self, data_types::NodeData, AppDB},
+    blockchain::db::{self as blockchain_db, NeedSignData, NodeActivation},
+    database::{
+        self,
+        data_types::{NodeActiveStatus, NodeData, NodeType},
+        AppDB,
+    },
 };
 
+/// Key of the voter set in the node trie, peer ids are base58 so it never collides with a node.
+pub const KEY_VOTERS: &[u8; 6] = b"voters";
+/// Prefix of the index from a peer id to the hashes of its activations, kept in the activation
+/// trie.
+pub const KEY_PEER_ACTIVATIONS: &[u8; 5] = b"peer:";
+
 #[derive(Clone)]
 pub struct NodeDB {
     pub db: Arc<dyn KeyValueDB>,
@@ -21,14 +32,29 @@ pub struct NodeDB {
 
 impl NodeDB {
     pub fn new(db: Arc<dyn KeyValueDB>) -> Result<Self> {
-        let node_root = database::get_root(&db, database::db::COL_NODE_LIST)?;
+        // Reopen the state at the roots of the head block.
+        let (node_root, node_active_root) = match blockchain_db::get_latest_header(db.clone())? {
+            Some(header) => (header.node_root, header.node_activation_root),
+            None => (
+                database::get_root(&db, database::db::COL_NODE_LIST)?,
+                database::get_root(&db, database::db::COL_NODE_LIST_ACTIVATED)?,
+            ),
+        };
+        Self::with_roots(db, node_root, node_active_root)
+    }
+
+    /// `with_roots` open the node state at the given roots.
+    pub fn with_roots(
+        db: Arc<dyn KeyValueDB>,
+        node_root: H256,
+        node_active_root: H256,
+    ) -> Result<Self> {
         let node_db = AppDB::new(
             db.clone(),
             database::db::COL_NODE_LIST,
             node_root.to_fixed_bytes(),
         )?;
 
-        let node_active_root = database::get_root(&db, database::db::COL_NODE_LIST_ACTIVATED)?;
         let node_active_db = AppDB::new(
             db.clone(),
             database::db::COL_NODE_LIST_ACTIVATED,
@@ -56,11 +82,66 @@ impl NodeDB {
         })
     }
 
+    /// `insert_node` insert or replace the node, nodes are keyed by peer id.
     pub fn insert_node(&mut self, node: NodeData) -> Result<()> {
-        let node_bytes = serde_cbor::to_vec(&node)?;
-        let node_bytes_hash = KeccakHasher::hash(&node_bytes);
-        self.node_db.insert(&node_bytes_hash, &node_bytes)?;
-        self.temp_node_db.insert(&node_bytes_hash, &node_bytes)?;
+        let node_bytes = node.encode();
+        self.node_db.insert(node.peer_id.as_bytes(), &node_bytes)?;
+        self.temp_node_db
+            .insert(node.peer_id.as_bytes(), &node_bytes)?;
+        Ok(())
+    }
+
+    pub fn get_node(&self, peer_id: &str) -> Result<Option<NodeData>> {
+        match self.node_db.get(peer_id.as_bytes())? {
+            Some(node_bytes) => Ok(Some(NodeData::decode(&mut node_bytes.as_slice())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// `list_nodes` the nodes in peer id order from the start peer id on, at most `limit` nodes.
+    pub fn list_nodes(&self, start_peer_id: Option<&str>, limit: usize) -> Result<Vec<NodeData>> {
+        let mut nodes = vec![];
+        let mut start = start_peer_id.map(|p| p.as_bytes().to_vec());
+        // the voter set shares the trie, skip it and fetch the following items
+        while nodes.len() < limit {
+            let items = self
+                .node_db
+                .list(&[], start.as_deref(), limit - nodes.len())?;
+            let last_key = match items.last() {
+                Some((key, _)) => key.clone(),
+                None => break,
+            };
+            for (key, node_bytes) in items {
+                if key != KEY_VOTERS {
+                    nodes.push(NodeData::decode(&mut node_bytes.as_slice())?);
+                }
+            }
+            start = Some([last_key.as_slice(), &[0u8]].concat());
+        }
+        Ok(nodes)
+    }
+
+    /// `get_voters` the voting rights of every verify node allowed to vote, keyed by peer id.
+    pub fn get_voters(&self) -> Result<BTreeMap<String, u128>> {
+        match self.node_db.get(KEY_VOTERS)? {
+            Some(voters) => Ok(BTreeMap::decode(&mut voters.as_slice())?),
+            None => Ok(BTreeMap::new()),
+        }
+    }
+
+    /// `update_voter` add the node to the voter set if it's an activated verify node with voting
+    /// rights, otherwise remove it.
+    pub fn update_voter(&mut self, node: &NodeData) -> Result<()> {
+        let mut voters = self.get_voters()?;
+        if node.node_type == NodeType::Verify
+            && node.active_status == NodeActiveStatus::Actived
+            && node.voting_rights > 0
+        {
+            voters.insert(node.peer_id.clone(), node.voting_rights);
+        } else {
+            voters.remove(&node.peer_id);
+        }
+        self.node_db.insert(KEY_VOTERS, &voters.encode())?;
         Ok(())
     }
 
@@ -68,12 +149,61 @@ impl NodeDB {
         &mut self,
         node_activation: NeedSignData<NodeActivation>,
     ) -> Result<()> {
-        let node_activation_bytes = serde_cbor::to_vec(&node_activation)?;
+        let node_activation_bytes = node_activation.encode();
         let node_activation_hash = KeccakHasher::hash(&node_activation_bytes);
         self.node_active_db
             .insert(&node_activation_hash, &node_activation_bytes)?;
         self.temp_node_active_db
             .insert(&node_activation_hash, &node_activation_bytes)?;
+
+        let peer_id = &node_activation.data.peer_id;
+        let mut hashes = self.get_activation_hashes(peer_id)?;
+        hashes.push(H256(node_activation_hash));
+        self.node_active_db
+            .insert(&peer_activations_key(peer_id), &hashes.encode())?;
+        Ok(())
+    }
+
+    fn get_activation_hashes(&self, peer_id: &str) -> Result<Vec<H256>> {
+        match self.node_active_db.get(&peer_activations_key(peer_id))? {
+            Some(hashes) => Ok(Vec::<H256>::decode(&mut hashes.as_slice())?),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// `get_node_activation` the activation stored under its hash.
+    pub fn get_node_activation(&self, hash: H256) -> Result<Option<NeedSignData<NodeActivation>>> {
+        match self.node_active_db.get(hash.as_bytes())? {
+            Some(data_bytes) => Ok(Some(NeedSignData::decode(&mut data_bytes.as_slice())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// `get_node_activations` the activations of the peer, in the order they were applied.
+    pub fn get_node_activations(&self, peer_id: &str) -> Result<Vec<NeedSignData<NodeActivation>>> {
+        let mut activations = vec![];
+        for hash in self.get_activation_hashes(peer_id)? {
+            match self.get_node_activation(hash)? {
+                Some(activation) => activations.push(activation),
+                None => return Err(format!("node activation {:?} not found", hash).into()),
+            }
+        }
+        Ok(activations)
+    }
+
+    /// `set_inline_threshold` set the inline value threshold of the chain on the state tries.
+    pub fn set_inline_threshold(&mut self, threshold: u32) -> Result<()> {
+        self.node_db.set_inline_threshold(threshold)?;
+        self.node_active_db.set_inline_threshold(threshold)?;
+        Ok(())
+    }
+
+    /// `commit` write the trie changes of every node trie, the temp tries only give the roots
+    /// of the current block and are never written.
+    pub fn commit(&mut self) -> Result<()> {
+        for app_db in [&mut self.node_db, &mut self.node_active_db] {
+            app_db.commit()?;
+        }
         Ok(())
     }
 
@@ -93,3 +223,67 @@ impl NodeDB {
         Ok(())
     }
 }
+
+fn peer_activations_key(peer_id: &str) -> Vec<u8> {
+    [&KEY_PEER_ACTIVATIONS[..], peer_id.as_bytes()].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::blockchain::db::ActivationOperation;
+
+    fn activation(peer_id: &str, operation: ActivationOperation) -> NeedSignData<NodeActivation> {
+        NeedSignData {
+            data: NodeActivation {
+                operation,
+                peer_id: peer_id.to_owned(),
+                account: vec![1],
+                pub_key: vec![2],
+                node_type: NodeType::Verify,
+            },
+            signature: vec![3],
+        }
+    }
+
+    #[test]
+    fn test_node_activations() {
+        let db = database::open_memory_database();
+        let mut node_db = NodeDB::new(db.clone()).unwrap();
+        let activations = vec![
+            activation("peer-a", ActivationOperation::Activate),
+            activation("peer-a", ActivationOperation::Deactivate),
+        ];
+        for activation in &activations {
+            node_db.insert_node_activation(activation.clone()).unwrap();
+        }
+        node_db
+            .insert_node_activation(activation("peer-b", ActivationOperation::Activate))
+            .unwrap();
+        node_db.commit().unwrap();
+
+        let node_db = NodeDB::with_roots(
+            db,
+            H256(node_db.node_db.get_root()),
+            H256(node_db.node_active_db.get_root()),
+        )
+        .unwrap();
+        assert_eq!(node_db.get_node_activations("peer-a").unwrap(), activations);
+        assert_eq!(node_db.get_node_activations("peer-b").unwrap().len(), 1);
+        assert!(node_db.get_node_activations("peer-c").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_node_activation_not_found() {
+        let mut node_db = NodeDB::new(database::open_memory_database()).unwrap();
+        let activation = activation("peer-a", ActivationOperation::Activate);
+        node_db.insert_node_activation(activation.clone()).unwrap();
+
+        // the index still lists the activation removed from the trie
+        let hash = KeccakHasher::hash(&activation.encode());
+        node_db.node_active_db.remove(&hash).unwrap();
+        let error = node_db.get_node_activations("peer-a").unwrap_err();
+        assert!(error.message.contains("not found"));
+    }
+}
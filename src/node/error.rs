@@ -5,6 +5,7 @@ use std::{fmt, result};
 use futures::channel::mpsc::SendError;
 use serde::Serialize;
 
+use crate::blockchain::error::BlockchainError;
 use crate::database;
 use crate::ic::error::ICError;
 use crate::message::MessageError;
@@ -43,3 +44,5 @@ quick_from!(ICError);
 quick_from!(String);
 quick_from!(database::error::DatabaseError);
 quick_from!(serde_cbor::Error);
+quick_from!(parity_scale_codec::Error);
+quick_from!(BlockchainError);
@@ -1,5 +1,5 @@
 use std::{
-    collections::{hash_map, HashMap},
+    collections::{hash_map, BTreeMap, HashMap},
     str::FromStr,
     sync::Arc,
     thread,
@@ -18,7 +18,7 @@ use crate::{
         AppDB,
     },
     ic::{self, wdn_identity::WdnIdentity},
-    message::{Caller, LocalMessage, LocalMessageModule, Message, Waiter},
+    message::{Caller, LocalMessage, LocalMessageModule, Message, ReqError, Waiter},
     network::{
         topics::{self, PingMessage, SubTopics, TopicMessage},
         NetworkMessage, NetworkModule,
@@ -39,6 +39,7 @@ use ic_agent::{ic_types::Principal, Identity, Signature};
 use keccak_hasher::KeccakHasher;
 use kvdb::KeyValueDB;
 use libp2p::{gossipsub::GossipsubMessage, identity::Keypair, PeerId};
+use parity_scale_codec::Encode;
 use rand::prelude::SliceRandom;
 use serde::{Deserialize, Serialize};
 use topics::Topics;
@@ -94,7 +95,7 @@ impl NodeModule {
         )
         .expect("create agent fail!");
 
-        Ok(NodeModule {
+        let mut node_module = NodeModule {
             config,
             local_key: local_key,
             peer_id: peer_id,
@@ -112,7 +113,25 @@ impl NodeModule {
             last_distribute_block: 0,
             task_distribute_list: vec![],
             agent: agent,
-        })
+        };
+        node_module.load_node_list()?;
+        Ok(node_module)
+    }
+
+    /// `load_node_list` refresh the node list with the nodes of the head block state,
+    /// the online peers not stored yet are kept.
+    fn load_node_list(&mut self) -> Result<()> {
+        for node in self.node_db.list_nodes(None, usize::MAX)? {
+            match self
+                .node_list
+                .iter_mut()
+                .find(|n| n.peer_id == node.peer_id)
+            {
+                Some(known) => *known = node,
+                None => self.node_list.push(node),
+            }
+        }
+        Ok(())
     }
 
     pub async fn require_active_status_from_verify_node(
@@ -134,7 +153,7 @@ impl NodeModule {
     }
 
     async fn verify_node_init(&mut self) -> Result<()> {
-        // Check current block is genesis block.
+        // Check the chain only has the genesis block.
         let current_block_res = self
             .blockchain_caller
             .clone()
@@ -151,7 +170,7 @@ impl NodeModule {
                     message: "Can't get block info now!".to_owned(),
                 });
             };
-        if current_block.header.index != 0 {
+        if current_block.header.index != 1 {
             return Err(NodeError {
                 message: "node had inited".to_owned(),
             });
@@ -174,14 +193,15 @@ impl NodeModule {
             });
         }
 
-        // Add current node active operation into genesis block
+        // Add current node active operation into the first block
         let node_active_operation_data = NodeActivation {
             operation: crate::blockchain::db::ActivationOperation::Activate,
             peer_id: self.peer_id.to_base58(),
             account: serde_cbor::to_vec(&self.config.principal_id)?,
             pub_key: self.local_key.public().to_protobuf_encoding(),
+            node_type: NodeType::Verify,
         };
-        let node_active_operation_data_bytes = serde_cbor::to_vec(&node_active_operation_data)?;
+        let node_active_operation_data_bytes = node_active_operation_data.encode();
         let node_active_operation_signature = self
             .wdn_indentity
             .sign(&node_active_operation_data_bytes)?
@@ -231,26 +251,7 @@ impl NodeModule {
             };
         if !res {
             return Err(NodeError {
-                message: "Init genesis block fail!".to_owned(),
-            });
-        }
-
-        let req_task_init_genesis_res = self
-            .task_caller
-            .clone()
-            .expect("can't get task caller")
-            .call(Message::LocalMessage(LocalMessage::ReqTaskInitGenesis()))
-            .await?;
-        let res = if let Some(Message::LocalMessage(LocalMessage::AckTaskInitGenesis(res))) =
-            req_task_init_genesis_res
-        {
-            res
-        } else {
-            false
-        };
-        if !res {
-            return Err(NodeError {
-                message: "init genesis block fail!".to_owned(),
+                message: "save node activation fail!".to_owned(),
             });
         }
 
@@ -428,10 +429,10 @@ pub fn deal_peer_message(node: &mut NodeModule, msg: &TopicMessage) {
                     active_status: NodeActiveStatus::Inactived,
                     node_type: NodeType::Work,
                     stake_amount: 0,
-                    worker_stake_amount: HashMap::new(),
+                    worker_stake_amount: BTreeMap::new(),
                     vote_amount: 0,
                     voting_rights: 0,
-                    worker_vote_amount: HashMap::new(),
+                    worker_vote_amount: BTreeMap::new(),
                     online_blocks: 0,
                 })
             };
@@ -452,6 +453,32 @@ pub async fn deal_local_message(node: &mut NodeModule, msg: &LocalMessage) -> Op
             }
             None
         }
+        LocalMessage::BlockNewHead(header) => {
+            let node_db = NodeDB::with_roots(
+                node.node_db.db.clone(),
+                header.node_root,
+                header.node_activation_root,
+            );
+            match node_db {
+                Ok(node_db) => node.node_db = node_db,
+                Err(e) => log::error!("reopen node db at new head fail: {:?}", e),
+            }
+            if let Err(e) = node.load_node_list() {
+                log::error!("load node list fail: {:?}", e);
+            }
+            None
+        }
+        LocalMessage::ReqNodePage(start_peer_id, limit) => {
+            match node.node_db.list_nodes(start_peer_id.as_deref(), *limit) {
+                Ok(nodes) => Some(Message::LocalMessage(LocalMessage::AckNodePage(nodes))),
+                Err(e) => {
+                    log::error!("list nodes fail: {:?}", e);
+                    Some(Message::LocalMessage(LocalMessage::AckError(
+                        ReqError::Internal(e.message),
+                    )))
+                }
+            }
+        }
         LocalMessage::ReqKeeperInit() => {
             let res = node.verify_node_init().await;
             if res.is_ok() {
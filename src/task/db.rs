@@ -4,14 +4,18 @@ use ethereum_types::H256;
 use hash_db::Hasher;
 use keccak_hasher::KeccakHasher;
 use kvdb::KeyValueDB;
+use parity_scale_codec::{Decode, Encode};
 
 use crate::{
-    blockchain::db::{TaskOperation, TaskResult},
+    blockchain::db::{self as blockchain_db, TaskOperation, TaskResult},
     database::{self, data_types::TaskData, AppDB},
 };
 
 use super::error::Result;
 
+/// Prefix of the index from a task id to the hashes of its operations, kept in the operation trie.
+pub const KEY_TASK_OPERATIONS: &[u8; 5] = b"task:";
+
 #[derive(Clone)]
 pub struct TaskDB {
     pub db: Arc<dyn KeyValueDB>,
@@ -24,21 +28,42 @@ pub struct TaskDB {
 
 impl TaskDB {
     pub fn new(db: Arc<dyn KeyValueDB>) -> Result<Self> {
-        let task_root = database::get_root(&db, database::db::COL_TASK_LIST)?;
+        // Reopen the state at the roots of the head block.
+        let (task_root, task_operation_root, task_result_root) =
+            match blockchain_db::get_latest_header(db.clone())? {
+                Some(header) => (
+                    header.task_root,
+                    header.task_operation_root,
+                    header.task_result_root,
+                ),
+                None => (
+                    database::get_root(&db, database::db::COL_TASK_LIST)?,
+                    database::get_root(&db, database::db::COL_TASK_OPERATIONS)?,
+                    database::get_root(&db, database::db::COL_TASK_RESULT)?,
+                ),
+            };
+        Self::with_roots(db, task_root, task_operation_root, task_result_root)
+    }
+
+    /// `with_roots` open the task state at the given roots.
+    pub fn with_roots(
+        db: Arc<dyn KeyValueDB>,
+        task_root: H256,
+        task_operation_root: H256,
+        task_result_root: H256,
+    ) -> Result<Self> {
         let task_db = AppDB::new(
             db.clone(),
             database::db::COL_TASK_LIST,
             task_root.to_fixed_bytes(),
         )?;
 
-        let task_operation_root = database::get_root(&db, database::db::COL_TASK_OPERATIONS)?;
         let task_operation_db = AppDB::new(
             db.clone(),
             database::db::COL_TASK_OPERATIONS,
             task_operation_root.to_fixed_bytes(),
         )?;
 
-        let task_result_root = database::get_root(&db, database::db::COL_TASK_RESULT)?;
         let task_result_db = AppDB::new(
             db.clone(),
             database::db::COL_TASK_RESULT,
@@ -67,26 +92,170 @@ impl TaskDB {
         })
     }
 
+    /// `insert_task` insert or replace the task, tasks are keyed by task id.
     pub fn insert_task(&mut self, task: TaskData) -> Result<()> {
-        let task_bytes = serde_cbor::to_vec(&task)?;
-        let hash = KeccakHasher::hash(&task_bytes);
-        self.task_db.insert(&hash, &task_bytes)?;
+        self.task_db.insert(&task_key(task.id), &task.encode())?;
+        Ok(())
+    }
+
+    pub fn get_task(&self, id: u64) -> Result<Option<TaskData>> {
+        match self.task_db.get(&task_key(id))? {
+            Some(task_bytes) => Ok(Some(TaskData::decode(&mut task_bytes.as_slice())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// `list_tasks` the tasks in id order from the start id on, at most `limit` tasks.
+    pub fn list_tasks(&self, start_id: Option<u64>, limit: usize) -> Result<Vec<TaskData>> {
+        let start = start_id.map(task_key);
+        let mut tasks = vec![];
+        for (_, task_bytes) in self
+            .task_db
+            .list(&[], start.as_ref().map(|k| &k[..]), limit)?
+        {
+            tasks.push(TaskData::decode(&mut task_bytes.as_slice())?);
+        }
+        Ok(tasks)
+    }
+
+    pub fn remove_task(&mut self, id: u64) -> Result<()> {
+        self.task_db.remove(&task_key(id))?;
         Ok(())
     }
 
+    /// `insert_task_operation` store the operation by its hash, and index it by the task id.
     pub fn insert_task_operation(&mut self, task_operation: TaskOperation) -> Result<()> {
-        let data_bytes = serde_cbor::to_vec(&task_operation)?;
+        let data_bytes = task_operation.encode();
         let hash = KeccakHasher::hash(&data_bytes);
         self.task_operation_db.insert(&hash, &data_bytes)?;
         self.temp_task_operation_db.insert(&hash, &data_bytes)?;
+
+        let index_key = task_operations_key(task_operation.id);
+        let mut hashes = self.get_task_operation_hashes(task_operation.id)?;
+        hashes.push(H256(hash));
+        self.task_operation_db
+            .insert(&index_key, &hashes.encode())?;
+        Ok(())
+    }
+
+    fn get_task_operation_hashes(&self, id: u64) -> Result<Vec<H256>> {
+        match self.task_operation_db.get(&task_operations_key(id))? {
+            Some(hashes) => Ok(Vec::<H256>::decode(&mut hashes.as_slice())?),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// `get_task_operations` the operations applied on the task, in the order they were applied.
+    pub fn get_task_operations(&self, id: u64) -> Result<Vec<TaskOperation>> {
+        let mut operations = vec![];
+        for hash in self.get_task_operation_hashes(id)? {
+            match self.task_operation_db.get(hash.as_bytes())? {
+                Some(data_bytes) => {
+                    operations.push(TaskOperation::decode(&mut data_bytes.as_slice())?)
+                }
+                None => return Err(format!("task operation {:?} not found", hash).into()),
+            }
+        }
+        Ok(operations)
+    }
+
+    /// `set_inline_threshold` set the inline value threshold of the chain on the state tries.
+    pub fn set_inline_threshold(&mut self, threshold: u32) -> Result<()> {
+        for app_db in [
+            &mut self.task_db,
+            &mut self.task_operation_db,
+            &mut self.task_result_db,
+        ] {
+            app_db.set_inline_threshold(threshold)?;
+        }
+        Ok(())
+    }
+
+    /// `commit` write the trie changes of every task trie, the temp tries only give the roots
+    /// of the current block and are never written.
+    pub fn commit(&mut self) -> Result<()> {
+        for app_db in [
+            &mut self.task_db,
+            &mut self.task_operation_db,
+            &mut self.task_result_db,
+        ] {
+            app_db.commit()?;
+        }
         Ok(())
     }
 
     pub fn insert_task_result(&mut self, task_result: TaskResult) -> Result<()> {
-        let data_bytes = serde_cbor::to_vec(&task_result)?;
+        let data_bytes = task_result.encode();
         let hash = KeccakHasher::hash(&data_bytes);
         self.task_result_db.insert(&hash, &data_bytes)?;
         self.temp_task_result_db.insert(&hash, &data_bytes)?;
         Ok(())
     }
 }
+
+fn task_key(id: u64) -> [u8; 8] {
+    id.to_be_bytes()
+}
+
+fn task_operations_key(id: u64) -> Vec<u8> {
+    [&KEY_TASK_OPERATIONS[..], &task_key(id)].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{blockchain::db::TaskOperationType, database::data_types::TaskType};
+
+    fn task_operation(id: u64, operation: TaskOperationType) -> TaskOperation {
+        TaskOperation {
+            id,
+            operation,
+            binary_hash: H256::repeat_byte(1),
+            task_type: TaskType::LongTerm,
+            node_limit: 100,
+            reward_weight: 100,
+        }
+    }
+
+    #[test]
+    fn test_task_operations() {
+        let db = database::open_memory_database();
+        let mut task_db = TaskDB::new(db.clone()).unwrap();
+        let operations = vec![
+            task_operation(1, TaskOperationType::Add),
+            task_operation(1, TaskOperationType::Disable),
+        ];
+        for operation in &operations {
+            task_db.insert_task_operation(operation.clone()).unwrap();
+        }
+        task_db
+            .insert_task_operation(task_operation(2, TaskOperationType::Add))
+            .unwrap();
+        task_db.commit().unwrap();
+
+        let task_db = TaskDB::with_roots(
+            db,
+            H256(task_db.task_db.get_root()),
+            H256(task_db.task_operation_db.get_root()),
+            H256(task_db.task_result_db.get_root()),
+        )
+        .unwrap();
+        assert_eq!(task_db.get_task_operations(1).unwrap(), operations);
+        assert_eq!(task_db.get_task_operations(2).unwrap().len(), 1);
+        assert!(task_db.get_task_operations(3).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_task_operation_not_found() {
+        let mut task_db = TaskDB::new(database::open_memory_database()).unwrap();
+        let operation = task_operation(1, TaskOperationType::Add);
+        task_db.insert_task_operation(operation.clone()).unwrap();
+
+        // the index still lists the operation removed from the trie
+        let hash = KeccakHasher::hash(&operation.encode());
+        task_db.task_operation_db.remove(&hash).unwrap();
+        let error = task_db.get_task_operations(1).unwrap_err();
+        assert!(error.message.contains("not found"));
+    }
+}
@@ -43,5 +43,6 @@ quick_from!(ICError);
 quick_from!(String);
 quick_from!(database::error::DatabaseError);
 quick_from!(serde_cbor::Error);
+quick_from!(parity_scale_codec::Error);
 quick_from!(BlockchainError);
 quick_from!(MessageError);
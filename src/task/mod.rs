@@ -1,9 +1,8 @@
-use std::{str::FromStr, sync::Arc, thread};
+use std::{sync::Arc, thread};
 
 use crate::{
-    blockchain::db::{TaskOperation, TaskOperationType},
     database::data_types::{TaskData, TaskDistributeData, TaskStatus, TaskType},
-    message::{Caller, LocalMessage, LocalMessageModule, Message, Waiter},
+    message::{Caller, LocalMessage, LocalMessageModule, Message, ReqError, Waiter},
     network::{
         topics::{self, SubTopics, TopicMessage},
         NetworkMessage, NetworkModule,
@@ -22,7 +21,7 @@ use kvdb::KeyValueDB;
 use log::info;
 use topics::Topics;
 
-use self::{db::TaskDB, error::TaskError};
+use self::db::TaskDB;
 
 pub mod config;
 pub mod db;
@@ -47,7 +46,7 @@ impl TaskModule {
         ];
 
         let task_db = TaskDB::new(db)?;
-        Ok(TaskModule {
+        let mut task_module = TaskModule {
             db: task_db,
             peer_id: peer_id,
             network_caller: message_waiter.get_caller(),
@@ -55,81 +54,14 @@ impl TaskModule {
             message_subscribe,
             all_task_list: vec![],
             running_task_list: vec![],
-        })
+        };
+        task_module.load_task_list()?;
+        Ok(task_module)
     }
 
-    async fn genesis_init_task(&mut self) -> Result<()> {
-        let task_one_add_operation = TaskOperation {
-            id: 1,
-            operation: TaskOperationType::Add,
-            binary_hash: H256::from_str("one").unwrap(),
-            task_type: TaskType::LongTerm,
-            node_limit: 100,
-            reward_weight: 100,
-        };
-        self.db
-            .insert_task_operation(task_one_add_operation.clone())?;
-        let task_one = TaskData {
-            id: task_one_add_operation.id.clone(),
-            hash: task_one_add_operation.binary_hash.clone(),
-            task_type: task_one_add_operation.task_type.clone(),
-            node_limit: task_one_add_operation.node_limit.clone(),
-            current_node_num: 0,
-            status: TaskStatus::Enable,
-            reward_weight: task_one_add_operation.reward_weight.clone(),
-        };
-        self.db.insert_task(task_one.clone())?;
-        let task_two_add_operation = TaskOperation {
-            id: 2,
-            operation: TaskOperationType::Add,
-            binary_hash: H256::from_str("two").unwrap(),
-            task_type: TaskType::LongTerm,
-            node_limit: 100,
-            reward_weight: 200,
-        };
-        self.db
-            .insert_task_operation(task_two_add_operation.clone())?;
-        let task_two = TaskData {
-            id: task_two_add_operation.id.clone(),
-            hash: task_two_add_operation.binary_hash.clone(),
-            task_type: task_two_add_operation.task_type.clone(),
-            node_limit: task_two_add_operation.node_limit.clone(),
-            current_node_num: 0,
-            status: TaskStatus::Enable,
-            reward_weight: task_two_add_operation.reward_weight.clone(),
-        };
-        self.db.insert_task(task_two.clone())?;
-        let task_root = self.db.task_db.get_root();
-        let task_operation_root = self.db.task_operation_db.get_root();
-        let temp_task_operation_root = self.db.temp_task_operation_db.get_root();
-        let res = self
-            .network_caller
-            .call(Message::LocalMessage(
-                LocalMessage::ReqBlockSaveTaskOperation(
-                    vec![
-                        task_one_add_operation.clone(),
-                        task_two_add_operation.clone(),
-                    ],
-                    H256(task_root),
-                    H256(task_operation_root),
-                    H256(temp_task_operation_root),
-                ),
-            ))
-            .await?;
-        let res = if let Some(Message::LocalMessage(LocalMessage::AckBlockSaveTaskOperation(res))) =
-            res
-        {
-            res
-        } else {
-            return Err(TaskError {
-                message: "init task fail".to_owned(),
-            });
-        };
-        if !res {
-            return Err(TaskError {
-                message: "init task fail".to_owned(),
-            });
-        }
+    /// `load_task_list` rebuild the task list from the task state of the head block.
+    fn load_task_list(&mut self) -> Result<()> {
+        self.all_task_list = self.db.list_tasks(None, usize::MAX)?;
         Ok(())
     }
 
@@ -299,11 +231,32 @@ async fn deal_local_message(task_module: &mut TaskModule, msg: &LocalMessage) ->
                 task_module.all_task_list.clone(),
             )))
         }
-        LocalMessage::ReqTaskInitGenesis() => {
-            log::info!("init genesis");
-            Some(Message::LocalMessage(LocalMessage::AckTaskInitGenesis(
-                true,
-            )))
+        LocalMessage::BlockNewHead(header) => {
+            let task_db = TaskDB::with_roots(
+                task_module.db.db.clone(),
+                header.task_root,
+                header.task_operation_root,
+                header.task_result_root,
+            );
+            match task_db {
+                Ok(task_db) => task_module.db = task_db,
+                Err(e) => log::error!("reopen task db at new head fail: {:?}", e),
+            }
+            if let Err(e) = task_module.load_task_list() {
+                log::error!("load task list fail: {:?}", e);
+            }
+            None
+        }
+        LocalMessage::ReqTaskPage(start_id, limit) => {
+            match task_module.db.list_tasks(*start_id, *limit) {
+                Ok(tasks) => Some(Message::LocalMessage(LocalMessage::AckTaskPage(tasks))),
+                Err(e) => {
+                    log::error!("list tasks fail: {:?}", e);
+                    Some(Message::LocalMessage(LocalMessage::AckError(
+                        ReqError::Internal(e.message),
+                    )))
+                }
+            }
         }
         _ => None,
     }
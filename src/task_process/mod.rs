@@ -6,9 +6,11 @@ use std::{
     sync::Arc,
 };
 
+use chrono::Local;
 use futures::{channel::mpsc::Sender, SinkExt};
 
 use crate::{
+    blockchain::db::TaskResult,
     database::{data_types::TaskData, AppDB},
     message::{Caller, Message},
     network::{
@@ -110,9 +112,20 @@ impl TaskProcessServer {
     pub async fn deal_command_invoke(&mut self, command_invoke: TaskCommandInvoke) {
         match command_invoke.command {
             TaskCommand::UploadData => {
+                let task_id = match &self.task {
+                    Some(task) => task.id,
+                    None => return,
+                };
+                // The keeper fills the peer id from the message source.
+                let task_result = TaskResult {
+                    id: task_id,
+                    peer_id: String::new(),
+                    timestamp: Local::now().timestamp_millis() as u64,
+                    result: command_invoke.data,
+                };
                 let topic_msg = TopicMessage {
                     sub_topic: SubTopics::UploadTaskData,
-                    data: serde_cbor::to_vec(&command_invoke.data).unwrap(),
+                    data: serde_cbor::to_vec(&task_result).unwrap(),
                 };
                 let peer_msg = Message::NetworkMessage(NetworkMessage {
                     peer_id: None,